@@ -5,22 +5,25 @@ struct VertexOutput {
     @location(2) uv: vec2<f32>,
 };
 
-struct MyMat {
+struct HealthMaterial {
     color: vec4<f32>,
+    health: f32,
     time: f32,
 };
 
 @group(1) @binding(0)
-var<uniform> uniform_data: MyMat;
-@group(1) @binding(1)
-var texture: texture_2d<f32>;
-@group(1) @binding(2)
-var our_sampler: sampler;
+var<uniform> material: HealthMaterial;
 
 @fragment
 fn fragment(input: VertexOutput) -> @location(0) vec4<f32> {
-    var output_color = vec4<f32>(uniform_data.time, 1.0, 1.0, 1.0);
-    output_color = output_color * textureSample(texture, our_sampler, input.uv);
-    output_color = output_color * uniform_data.color;
-    return output_color;
-}
\ No newline at end of file
+    // uv.y is 0 at the top, so the bar fills up from the bottom
+    let filled = step(1.0 - material.health, input.uv.y);
+
+    // Pulse when health is running low
+    let danger = 1.0 - smoothstep(0.0, 0.3, material.health);
+    let pulse = 1.0 - 0.4 * danger * abs(sin(material.time * 6.0));
+
+    let empty = vec4<f32>(0.1, 0.1, 0.1, 0.7);
+    let full = vec4<f32>(material.color.rgb * pulse, 1.0);
+    return mix(empty, full, filled);
+}
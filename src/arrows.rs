@@ -2,7 +2,12 @@ use bevy::prelude::*;
 use bevy::render::extract_resource::ExtractResourcePlugin;
 use crate::shaders::target_arrow::ExtractedTime;
 use crate::ScoreResource;
+use crate::score::Judgement;
+use crate::chart::{Chart, SongConfig};
 use crate::consts::*;
+use crate::input::LaneInput;
+use crate::practice::{Practice, SeekEvent, SendSeek};
+use crate::settings::{save_settings, GamepadBindings, KeyBindings, Settings};
 use crate::time::ControlledTime;
 use crate::types::*;
 
@@ -11,7 +16,8 @@ pub struct ArrowMaterialResource {
     red_texture: Handle<Image>,
     blue_texture: Handle<Image>,
     green_texture: Handle<Image>,
-    border_texture: Handle<Image>
+    border_texture: Handle<Image>,
+    mine_texture: Handle<Image>,
 }
 
 impl FromWorld for ArrowMaterialResource {
@@ -22,12 +28,14 @@ impl FromWorld for ArrowMaterialResource {
         let blue_handle: Handle<Image> = asset_server.load("images/arrow_blue.png");
         let green_handle = asset_server.load("images/arrow_green.png");
         let border_handle = asset_server.load("images/arrow_border.png");
-        
+        let mine_handle = asset_server.load("images/arrow_mine.png");
+
         ArrowMaterialResource { 
             red_texture: red_handle.clone(),
             blue_texture: blue_handle.clone(),
             green_texture: green_handle.clone(),
-            border_texture: border_handle.clone()
+            border_texture: border_handle.clone(),
+            mine_texture: mine_handle,
         }
     }
 }
@@ -35,71 +43,230 @@ impl FromWorld for ArrowMaterialResource {
 #[derive(Component)]
 struct Arrow {
     speed: Speed,
-    direction: Directions
+    lane: usize,
+    kind: NoteKind,
+    /// Time at which the arrow should be pressed, judgement is based on it
+    click_time: f64,
+    /// Set once the arrow went past the target without being pressed, it just falls off after that
+    missed: bool,
+    hold: Option<Hold>,
+}
+
+/// State of a hold arrow
+struct Hold {
+    /// Time at which the key should be let go
+    end_time: f64,
+    /// Whether the start was hit and the key is being held
+    held: bool,
 }
 
+/// Tail drawn behind a hold arrow, child of the arrow
+#[derive(Component)]
+struct HoldTail;
+
+/// Height of the tail behind hold arrows
+const HOLD_TAIL_WIDTH: f32 = 50.;
+
+/// Seconds to wait after the last arrow is done before showing the results
+const RESULTS_DELAY: f64 = 2.;
+
 struct SpawnTimer(Timer);
 
+/// Starts the song with a fresh score
+fn reset_score(mut score: ResMut<ScoreResource>) {
+    *score = ScoreResource::default();
+}
+
+/// The chart's arrows from `from` seconds into it on, timed for the playback rate.
+/// The pending list is always rebuilt from the chart, which is left untouched.
+/// Arrows in lanes the playfield doesn't have, from a chart edited during the song, are left out
+fn chart_arrows(chart: &Chart, layout: LaneLayout, rate: f64, from: f64) -> impl Iterator<Item = ArrowTime> + '_ {
+    chart
+        .arrows
+        .iter()
+        .filter(move |arrow| arrow.click_time >= from && arrow.lane < layout.lanes())
+        .map(move |arrow| arrow.at_rate(rate))
+}
+
+/// Queues every arrow of the chart to be spawned
+fn setup_song_arrows(
+    mut song_config: ResMut<SongConfig>,
+    charts: Res<Assets<Chart>>,
+    layout: Res<LaneLayout>,
+    time: Res<ControlledTime>,
+) {
+    if let Some(chart) = charts.get(&song_config.chart) {
+        song_config.arrows = chart_arrows(chart, *layout, time.rate(), f64::NEG_INFINITY).collect();
+    }
+}
+
+/// Brings back the arrows from the point seeked to, clearing the ones on screen and the score
+#[allow(clippy::too_many_arguments)]
+fn seek_arrows(
+    mut commands: Commands,
+    mut seek_events: EventReader<SeekEvent>,
+    mut song_config: ResMut<SongConfig>,
+    charts: Res<Assets<Chart>>,
+    layout: Res<LaneLayout>,
+    time: Res<ControlledTime>,
+    mut score: ResMut<ScoreResource>,
+    arrows: Query<Entity, With<Arrow>>,
+) {
+    let seek = match seek_events.iter().last() {
+        Some(seek) => seek,
+        None => return,
+    };
+
+    for entity in arrows.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    *score = ScoreResource::default();
+
+    if let Some(chart) = charts.get(&song_config.chart) {
+        song_config.arrows = chart_arrows(chart, *layout, time.rate(), seek.seconds).collect();
+    }
+}
+
 fn spawn_arrows(
     mut commands: Commands,
     mut song_config: ResMut<SongConfig>,
     materials: Res<ArrowMaterialResource>,
+    layout: Res<LaneLayout>,
+    settings: Res<Settings>,
     time: Res<ControlledTime>,
 ) {
-    // We get the current time since startup (secs) and the time since the last iteration (secs_last),
-    // this way we check if any arrows should spawn th this window
-    
-    // Song starts 3 seconds after start, so we subtract 3 seconds
-    let secs = time.seconds_since_startup() - 3.;
-    let secs_last = secs -time.delta_seconds_f64();
-
-    // Counter of how many arrows we need to spawn and remove from the list
-    let mut remove_counter = 0;
-    for arrow in &song_config.arrows {
-        // List is ordered, so we can just check until an item fails
-        // Check if arrow should be spawned at any point between last frame and this frame
-        if secs_last < arrow.spawn_time && arrow.spawn_time < secs {
-            remove_counter += 1;
-
-            // Get the correct material according to speed
-            let material = match arrow.speed {
-                Speed::Slow => materials.red_texture.clone(),
-                Speed::Medium => materials.blue_texture.clone(),
-                Speed::Fast => materials.green_texture.clone(),
-            };
-
-            let mut transform =
-                Transform::from_translation(Vec3::new(SPAWN_POSITION, arrow.direction.y(), 1.));
-            transform.rotate(Quat::from_rotation_z(arrow.direction.rotation()));
-            commands
-                .spawn_bundle(SpriteBundle {
-                    texture: material,
-                    transform,
-                    sprite: Sprite { custom_size: Option::from(Vec2::new(140., 140.)), ..Default::default() },
-                    ..Default::default()
-                })
-                .insert(Arrow {
-                    speed: arrow.speed,
-                    direction: arrow.direction
-                });
-        } else {
-            break;
+    // We get the current time into the song (secs) and spawn every arrow whose time has come.
+    // Arrows due before the lead-in started spawn right away, already on their way to the target
+    let secs = time.song_seconds();
+    let scroll_speed = settings.scroll_speed;
+
+    // Spawn order changes with the scroll speed, so the whole list is checked
+    let due: Vec<ArrowTime> = song_config
+        .arrows
+        .iter()
+        .filter(|arrow| arrow.spawn_time(scroll_speed) < secs)
+        .copied()
+        .collect();
+    if due.is_empty() {
+        return;
+    }
+    song_config.arrows.retain(|arrow| arrow.spawn_time(scroll_speed) >= secs);
+
+    for arrow in &due {
+        // Get the correct material according to kind and speed
+        let (material, size) = match (arrow.kind, arrow.speed) {
+            (NoteKind::Mine, _) => (materials.mine_texture.clone(), 110.),
+            (NoteKind::Tap, Speed::Slow) => (materials.red_texture.clone(), 140.),
+            (NoteKind::Tap, Speed::Medium) => (materials.blue_texture.clone(), 140.),
+            (NoteKind::Tap, Speed::Fast) => (materials.green_texture.clone(), 140.),
+        };
+
+        let size = size * layout.scale();
+
+        let position = settings.scroll.place(SPAWN_POSITION, layout.y(arrow.lane));
+        let mut transform = Transform::from_translation(position.extend(1.));
+        transform.rotate(Quat::from_rotation_z(layout.rotation(arrow.lane, settings.scroll.rotation())));
+        let hold = arrow.hold.map(|duration| Hold {
+            end_time: arrow.click_time + duration,
+            held: false,
+        });
+        let mut entity = commands.spawn_bundle(SpriteBundle {
+            texture: material,
+            transform,
+            sprite: Sprite { custom_size: Option::from(Vec2::new(size, size)), ..Default::default() },
+            ..Default::default()
+        });
+
+        if hold.is_some() {
+            // Sized and placed by update_hold_tails
+            entity.with_children(|parent| {
+                parent
+                    .spawn_bundle(SpriteBundle {
+                        sprite: Sprite { color: Color::rgba(1., 1., 1., 0.6), ..Default::default() },
+                        ..Default::default()
+                    })
+                    .insert(HoldTail);
+            });
         }
+
+        entity.insert(Arrow {
+            speed: arrow.speed,
+            lane: arrow.lane,
+            kind: arrow.kind,
+            click_time: arrow.click_time,
+            missed: false,
+            hold,
+        });
     }
+}
+
+/// Rebuilds the arrows still to come when the chart file is edited during the song.
+/// Arrows already on screen and the score are left alone.
+/// The playfield isn't rebuilt, so an edit that changes the number of lanes waits for the song to be restarted
+fn reload_song_arrows(
+    mut chart_events: EventReader<AssetEvent<Chart>>,
+    mut song_config: ResMut<SongConfig>,
+    charts: Res<Assets<Chart>>,
+    layout: Res<LaneLayout>,
+    settings: Res<Settings>,
+    time: Res<ControlledTime>,
+) {
+    for event in chart_events.iter() {
+        if let AssetEvent::Modified { handle } = event {
+            if *handle != song_config.chart {
+                continue;
+            }
+
+            if let Some(chart) = charts.get(handle) {
+                if chart.layout != *layout {
+                    warn!(
+                        "{} now has {} lanes instead of {}, restart the song to reload it",
+                        chart.name,
+                        chart.layout.lanes(),
+                        layout.lanes()
+                    );
+                    continue;
+                }
 
-    for _ in 0..remove_counter {
-        song_config.arrows.remove(0);
+                let secs = time.song_seconds();
+
+                song_config.arrows = chart_arrows(chart, *layout, time.rate(), f64::NEG_INFINITY)
+                    .filter(|arrow| arrow.spawn_time(settings.scroll_speed) > secs)
+                    .collect();
+            }
+        }
     }
 }
 
-fn move_arrows(time: Res<ControlledTime>, mut query: Query<(&mut Transform, &Arrow)>) {
+/// Places arrows according to how long until they have to be pressed.
+/// Position is only for show, judgement goes by time.
+fn move_arrows(
+    time: Res<ControlledTime>,
+    layout: Res<LaneLayout>,
+    settings: Res<Settings>,
+    mut query: Query<(&mut Transform, &Arrow)>,
+) {
+    let secs = time.song_seconds();
+    let scroll = settings.scroll;
+
     for (mut transform, arrow) in query.iter_mut() {
-        transform.translation.x += time.delta_seconds() * arrow.speed.value();
+        let across = layout.y(arrow.lane);
+        let speed = settings.scroll_speed.value(arrow.speed);
+
+        // Held arrows stay on the target while their tail goes through it
+        if arrow.hold.as_ref().is_some_and(|hold| hold.held) {
+            transform.translation = scroll.place(TARGET_POSITION, across).extend(transform.translation.z);
+            continue;
+        }
+
+        // How far the arrow has gone on its way, from SPAWN_POSITION to TARGET_POSITION and past it
+        let along = TARGET_POSITION - (arrow.click_time - secs) as f32 * speed;
+        let mut position = scroll.place(along, across);
 
-        let distance_after_target = transform.translation.x - (TARGET_POSITION + THRESHOLD);
+        let distance_after_target = along - (TARGET_POSITION + THRESHOLD);
         if distance_after_target >= 0.02 {
-            // Move the arrow down if it's past the target
-            transform.translation.y -= time.delta_seconds() * distance_after_target * 2.;
+            // Make the arrow fall off faster and faster once it's past the target
+            position += scroll.fall() * distance_after_target.powi(2) / speed;
 
             // Change the scale according to how far away the arrow is
             let scale = ((100. - distance_after_target / 3.) / 100.).max(0.2);
@@ -110,23 +277,85 @@ fn move_arrows(time: Res<ControlledTime>, mut query: Query<(&mut Transform, &Arr
                 -distance_after_target * arrow.speed.multiplier() / 460.,
             ));
         }
+
+        transform.translation = position.extend(transform.translation.z);
+    }
+}
+
+/// Lets the player change the scroll speed during the song, arrows on screen move to match it.
+/// `+` and `-` go faster and slower, Tab switches between multiplier and constant speed
+fn change_scroll_speed(input: Res<Input<KeyCode>>, mut settings: ResMut<Settings>) {
+    let scroll_speed = settings.scroll_speed;
+    let changed = if input.any_just_pressed(FASTER_KEYS) {
+        scroll_speed.faster()
+    } else if input.any_just_pressed(SLOWER_KEYS) {
+        scroll_speed.slower()
+    } else if input.just_pressed(SCROLL_MODE_KEY) {
+        scroll_speed.toggle_mode()
+    } else {
+        return;
+    };
+
+    if changed != scroll_speed {
+        settings.scroll_speed = changed;
+    }
+}
+
+/// Keeps the scroll speed picked during the song, once the song is left
+fn save_scroll_speed(settings: Res<Settings>, keys: Res<KeyBindings>, gamepad: Res<GamepadBindings>) {
+    if settings.is_changed() {
+        save_settings(&settings, &keys, &gamepad);
+    }
+}
+
+/// Stretches each hold tail behind its arrow, undoing the arrow's rotation so it stays
+/// along the way arrows scroll
+fn update_hold_tails(
+    time: Res<ControlledTime>,
+    layout: Res<LaneLayout>,
+    settings: Res<Settings>,
+    arrows: Query<(&Arrow, &Transform), Without<HoldTail>>,
+    mut tails: Query<(&Parent, &mut Sprite, &mut Transform), With<HoldTail>>,
+) {
+    let secs = time.song_seconds();
+
+    for (parent, mut sprite, mut transform) in tails.iter_mut() {
+        let (arrow, arrow_transform) = match arrows.get(parent.get()) {
+            Ok(arrow) => arrow,
+            Err(_) => continue,
+        };
+
+        // While held, the tail shrinks as it goes through the target
+        let length = arrow.hold.as_ref().map_or(0., |hold| {
+            let start = if hold.held { secs } else { arrow.click_time };
+            ((hold.end_time - start) as f32 * settings.scroll_speed.value(arrow.speed)).max(0.)
+        });
+
+        let rotation = arrow_transform.rotation.inverse() * Quat::from_rotation_z(settings.scroll.rotation());
+        sprite.custom_size = Some(Vec2::new(length, HOLD_TAIL_WIDTH * layout.scale()));
+        transform.rotation = rotation;
+        transform.translation = rotation * Vec3::new(-length / 2., 0., -0.5);
     }
 }
 
 #[derive(Component)]
 struct TargetArrow;
 
-fn setup_target_arrows(mut commands: Commands, materials: Res<ArrowMaterialResource>) {
-    use Directions::*;
-    let directions = [Up, Down, Left, Right];
+fn setup_target_arrows(
+    mut commands: Commands,
+    materials: Res<ArrowMaterialResource>,
+    layout: Res<LaneLayout>,
+    settings: Res<Settings>,
+) {
+    let size = 140. * layout.scale();
 
-    for direction in directions.iter() {
-        let mut transform =
-            Transform::from_translation(Vec3::new(TARGET_POSITION, direction.y(), 1.));
-        transform.rotate(Quat::from_rotation_z(direction.rotation()));
+    for lane in layout.iter() {
+        let position = settings.scroll.place(TARGET_POSITION, layout.y(lane));
+        let mut transform = Transform::from_translation(position.extend(1.));
+        transform.rotate(Quat::from_rotation_z(layout.rotation(lane, settings.scroll.rotation())));
         commands.spawn_bundle(SpriteBundle {
             texture: materials.border_texture.clone(),
-            sprite: Sprite { custom_size: Option::from(Vec2::new(140., 140.)), ..Default::default() },
+            sprite: Sprite { custom_size: Option::from(Vec2::new(size, size)), ..Default::default() },
             transform,
             ..Default::default()
         })
@@ -134,39 +363,165 @@ fn setup_target_arrows(mut commands: Commands, materials: Res<ArrowMaterialResou
     }
 }
 
+/// Pulses the target arrows on every beat, for charts that have a bpm
+fn pulse_target_arrows(
+    time: Res<ControlledTime>,
+    song_config: Res<SongConfig>,
+    charts: Res<Assets<Chart>>,
+    mut query: Query<&mut Transform, With<TargetArrow>>,
+) {
+    let timing = match charts.get(&song_config.chart).and_then(|chart| chart.timing.as_ref()) {
+        Some(timing) => timing,
+        None => return,
+    };
+
+    let beat = timing.seconds_to_beat(time.chart_seconds());
+
+    // Biggest right on the beat, shrinking back until the next one
+    let scale = 1. + 0.08 * (1. - beat.rem_euclid(1.)).powi(4) as f32;
+    for mut transform in query.iter_mut() {
+        transform.scale = Vec3::splat(scale);
+    }
+}
+
+/// Removes the arrows left on screen and the target arrows
+fn tear_down_arrows(
+    mut commands: Commands,
+    arrows: Query<Entity, With<Arrow>>,
+    target_arrows: Query<Entity, With<TargetArrow>>,
+) {
+    for entity in arrows.iter().chain(target_arrows.iter()) {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
 pub struct CorrectArrowEvent {
-    pub direction: Directions,
-    pub points: usize,
+    pub lane: usize,
+    pub judgement: Judgement,
 }
 
-/// Despawns arrows when they reach the end if the correct button is clicked
+/// Sent when an arrow goes past the target without being pressed, or a hold is let go too early
+pub struct MissedArrowEvent;
+
+/// Sent when a mine is pressed
+pub struct MineHitEvent;
+
+/// Judges arrows against the song time when their key is pressed, and despawns them
+/// once they're done. Hold arrows stay until their key is let go, or until the end of the hold.
+#[allow(clippy::too_many_arguments)]
 fn despawn_arrows(
     mut commands: Commands,
-    query: Query<(Entity, &Transform, &Arrow)>,
-    keyboard_input: Res<Input<KeyCode>>,
+    mut query: Query<(Entity, &mut Arrow)>,
+    time: Res<ControlledTime>,
+    settings: Res<Settings>,
+    input: Res<LaneInput>,
     mut score: ResMut<ScoreResource>,
     mut correct_arrow_events: ResMut<Events<CorrectArrowEvent>>,
+    mut missed_arrow_events: EventWriter<MissedArrowEvent>,
+    mut mine_hit_events: EventWriter<MineHitEvent>,
 ) {
-    for (entity, transform, arrow) in query.iter() {
-        let pos = transform.translation.x;
+    let secs = time.song_seconds();
+    let windows = &settings.windows;
 
-        // Check if arrow is inside clicked threshold
-        if (TARGET_POSITION - THRESHOLD..=TARGET_POSITION + THRESHOLD).contains(&pos)
-            && arrow.direction.key_jest_pressed(&keyboard_input)
-        {
-            commands.entity(entity).despawn();
+    for (entity, mut arrow) in query.iter_mut() {
+        let lane = arrow.lane;
+
+        // Arrows that are done just fall off the screen
+        if arrow.missed {
+            // Same as having gone twice as far as the target along the way
+            if (secs - arrow.click_time) as f32 * settings.scroll_speed.value(arrow.speed) >= TARGET_POSITION {
+                commands.entity(entity).despawn_recursive();
+            }
+            continue;
+        }
+
+        if let Some(hold) = arrow.hold.as_ref().filter(|hold| hold.held) {
+            // Seconds until the end of the hold
+            let offset = hold.end_time - secs;
+
+            // Held all the way through, or let go early
+            if !input.pressed(lane) || offset <= 0. {
+                commands.entity(entity).despawn_recursive();
+
+                // Holding until the end is a perfect hold, letting go early is judged by how early
+                let judgement = if offset <= 0. { Some(Judgement::Perfect) } else { windows.judge(offset) };
+                match judgement {
+                    Some(judgement) => {
+                        score.increase_holds(judgement);
+                        correct_arrow_events.send(CorrectArrowEvent { lane, judgement });
+                    }
+                    None => {
+                        score.increase_dropped_holds();
+                        missed_arrow_events.send(MissedArrowEvent);
+                    }
+                }
+            }
+            continue;
+        }
 
-            let points = score.increase_correct(TARGET_POSITION - pos);
+        // Seconds until the arrow has to be pressed, negative once it's late
+        let offset = arrow.click_time - secs;
+        let judgement = windows
+            .judge(offset)
+            .filter(|_| input.just_pressed(lane));
+        let too_late = offset < -windows.hit_window();
 
-            correct_arrow_events.send(CorrectArrowEvent { direction: arrow.direction, points });
+        if arrow.kind == NoteKind::Mine {
+            // Mines are judged the other way round, letting them through is fine
+            if judgement.is_some() {
+                commands.entity(entity).despawn_recursive();
 
-            score.increase_correct(TARGET_POSITION - pos);
+                score.increase_mines_hit();
+                mine_hit_events.send(MineHitEvent);
+            } else if too_late {
+                arrow.missed = true;
+            }
+            continue;
         }
 
-        if pos >= 2. * TARGET_POSITION {
-            commands.entity(entity).despawn();
+        if let Some(judgement) = judgement {
+            match arrow.hold.as_mut() {
+                // Start holding, keeping the arrow on the target
+                Some(hold) => hold.held = true,
+                None => commands.entity(entity).despawn_recursive(),
+            }
+
+            score.increase_correct(judgement);
+
+            correct_arrow_events.send(CorrectArrowEvent { lane, judgement });
+        } else if too_late {
+            arrow.missed = true;
 
             score.increase_fails();
+            missed_arrow_events.send(MissedArrowEvent);
+        }
+    }
+}
+
+/// Ends the song once every arrow has spawned and been judged.
+/// Practice goes back to the start of the section instead
+fn end_song(
+    time: Res<ControlledTime>,
+    song_config: Res<SongConfig>,
+    query: Query<&Arrow>,
+    practice: Option<Res<Practice>>,
+    mut finished_at: Local<Option<f64>>,
+    mut state: ResMut<State<AppState>>,
+    mut seek_events: EventWriter<SeekEvent>,
+) {
+    let secs = time.seconds_since_startup();
+
+    if !song_config.arrows.is_empty() || !query.is_empty() {
+        *finished_at = None;
+        return;
+    }
+
+    // Give the last judgement some time on screen
+    let finished_at = *finished_at.get_or_insert(secs);
+    if secs - finished_at >= RESULTS_DELAY {
+        match practice {
+            Some(practice) => seek_events.send(SeekEvent { seconds: practice.restart_point() }),
+            None => state.set(AppState::Results).expect("Couldn't switch to state Results"),
         }
     }
 }
@@ -176,17 +531,32 @@ impl Plugin for ArrowsPlugins {
     fn build(&self, app: &mut App) {
         app.init_resource::<ArrowMaterialResource>()
            .init_resource::<Events<CorrectArrowEvent>>()
+           .add_event::<MissedArrowEvent>()
+           .add_event::<MineHitEvent>()
            .add_plugin(ExtractResourcePlugin::<ExtractedTime>::default())
            .insert_resource(SpawnTimer(Timer::from_seconds(1.0, true)))
            .add_system_set(
                 SystemSet::on_enter(AppState::Game)
                     .with_system(setup_target_arrows)
+                    .with_system(setup_song_arrows)
+                    .with_system(reset_score)
+            )
+           .add_system_set(
+                SystemSet::on_exit(AppState::Game)
+                    .with_system(tear_down_arrows)
+                    .with_system(save_scroll_speed)
             )
            .add_system_set(
                 SystemSet::on_update(AppState::Game)
+                    .with_system(reload_song_arrows.before(spawn_arrows))
+                    .with_system(seek_arrows.after(SendSeek).before(spawn_arrows))
                     .with_system(spawn_arrows)
                     .with_system(despawn_arrows)
+                    .with_system(change_scroll_speed.before(move_arrows))
                     .with_system(move_arrows)
+                    .with_system(update_hold_tails.after(move_arrows))
+                    .with_system(pulse_target_arrows)
+                    .with_system(end_song.after(despawn_arrows).label(SendSeek))
            );
     }
 }
\ No newline at end of file
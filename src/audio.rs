@@ -1,19 +1,346 @@
-use crate::{types::SongConfig, consts::AppState, time::ControlledTime};
-use bevy::prelude::*;
+use std::{
+    io::Cursor,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-fn start_song(audio: Res<Audio>, time: Res<ControlledTime>, config: Res<SongConfig>) {
-    // Soing starts 3 seconds after real time
-    let secs = time.seconds_since_startup();
-    let secs_last = secs - time.delta_seconds_f64();
+use crate::{
+    chart::{Chart, SongConfig},
+    consts::{AppState, PRACTICE_LEAD_IN},
+    practice::{SeekEvent, SendSeek},
+    time::{update_time, ControlledTime},
+};
+use bevy::{
+    audio::{play_queued_audio_system, AudioOutput, AudioSink},
+    prelude::*,
+    reflect::TypeUuid,
+};
+use rodio::Source;
 
-    if secs_last <= 3. && 3. <= secs {
-        audio.play(config.song_audio.clone());
+/// How far the song has been played, shared with the audio thread
+#[derive(Clone, Default)]
+pub struct SongPosition(Arc<PlayedSamples>);
+
+#[derive(Default)]
+struct PlayedSamples {
+    samples: AtomicU64,
+    sample_rate: AtomicU32,
+    channels: AtomicU32,
+}
+
+impl SongPosition {
+    /// Starts counting again from the beginning of a song with this format
+    fn start(&self, sample_rate: u32, channels: u16) {
+        self.0.samples.store(0, Ordering::Relaxed);
+        self.0.sample_rate.store(sample_rate, Ordering::Relaxed);
+        self.0.channels.store(channels as u32, Ordering::Relaxed);
+    }
+
+    fn add_sample(&self) {
+        self.add_samples(1);
+    }
+
+    fn add_samples(&self, samples: u64) {
+        self.0.samples.fetch_add(samples, Ordering::Relaxed);
+    }
+
+    /// Seconds of the song played so far, `None` until it starts playing
+    pub fn seconds(&self) -> Option<f64> {
+        let samples = self.0.samples.load(Ordering::Relaxed);
+        let samples_per_second =
+            self.0.sample_rate.load(Ordering::Relaxed) as f64 * self.0.channels.load(Ordering::Relaxed) as f64;
+
+        if samples == 0 || samples_per_second == 0. {
+            return None;
+        }
+        Some(samples as f64 / samples_per_second)
+    }
+}
+
+/// Song audio that keeps track of how much of it has been played
+#[derive(TypeUuid)]
+#[uuid = "3d1f6a52-7b0e-4c9d-8e24-5a6f0b9c1d73"]
+pub struct SongAudio {
+    source: AudioSource,
+    position: SongPosition,
+    /// Playback rate, the song plays faster or slower and its pitch goes with it
+    rate: f32,
+    /// Seconds into the song to start playing from
+    start: f64,
+}
+
+impl Decodable for SongAudio {
+    type Decoder = rodio::source::Speed<PositionDecoder>;
+    type DecoderItem = <PositionDecoder as Iterator>::Item;
+
+    fn decoder(&self) -> Self::Decoder {
+        let inner = self.source.decoder();
+        self.position.start(inner.sample_rate(), inner.channels());
+
+        let skip = (self.start * inner.sample_rate() as f64) as usize * inner.channels() as usize;
+        let decoder = PositionDecoder { inner, position: self.position.clone(), skip };
+
+        // Samples are counted before the rate applies, so the position stays in song seconds
+        decoder.speed(self.rate)
+    }
+}
+
+/// Decodes the song, counting every sample handed to the audio device
+pub struct PositionDecoder {
+    inner: rodio::Decoder<Cursor<AudioSource>>,
+    position: SongPosition,
+    /// Samples to skip before the first one is played, to start partway into the song
+    skip: usize,
+}
+
+impl Iterator for PositionDecoder {
+    type Item = <rodio::Decoder<Cursor<AudioSource>> as Iterator>::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Skipped on the audio thread, decoding up to a point late in the song takes a while.
+        // Skipped samples are counted too, so the position starts where the song does
+        if self.skip > 0 {
+            let skipped = self.inner.by_ref().take(self.skip).count();
+            self.position.add_samples(skipped as u64);
+            self.skip = 0;
+        }
+
+        let sample = self.inner.next();
+        if sample.is_some() {
+            self.position.add_sample();
+        }
+        sample
+    }
+}
+
+impl Source for PositionDecoder {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+/// Sample rate of the metronome clicks
+const METRONOME_SAMPLE_RATE: u32 = 44100;
+
+/// Seconds each metronome click lasts
+const CLICK_SECONDS: f32 = 0.03;
+
+/// Pitch of the metronome clicks
+const CLICK_FREQUENCY: f32 = 1000.;
+
+/// Endless clicks at a steady tempo, keeping track of how far they've been played
+#[derive(TypeUuid)]
+#[uuid = "b2c84e17-95d3-4a6f-a0e8-7f13c6d25b49"]
+pub struct Metronome {
+    /// Seconds between clicks
+    pub interval: f64,
+    pub position: SongPosition,
+}
+
+impl Decodable for Metronome {
+    type Decoder = MetronomeSource;
+    type DecoderItem = f32;
+
+    fn decoder(&self) -> Self::Decoder {
+        self.position.start(METRONOME_SAMPLE_RATE, 1);
+
+        MetronomeSource { interval: self.interval, sample: 0, position: self.position.clone() }
+    }
+}
+
+pub struct MetronomeSource {
+    interval: f64,
+    sample: u64,
+    position: SongPosition,
+}
+
+impl Iterator for MetronomeSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let seconds = self.sample as f64 / METRONOME_SAMPLE_RATE as f64;
+        let since_click = seconds.rem_euclid(self.interval) as f32;
+        self.sample += 1;
+        self.position.add_sample();
+
+        // Short beep that fades out
+        if since_click < CLICK_SECONDS {
+            let fade = 1. - since_click / CLICK_SECONDS;
+            Some((since_click * CLICK_FREQUENCY * std::f32::consts::TAU).sin() * fade * 0.5)
+        } else {
+            Some(0.)
+        }
+    }
+}
+
+impl Source for MetronomeSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        METRONOME_SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Sink of the song being played, kept so the song can be stopped
+struct SongSink(Handle<AudioSink>);
+
+/// Plays the song from `start` seconds into it, at the playback rate
+fn play_song(
+    commands: &mut Commands,
+    audio: &Audio<SongAudio>,
+    audio_sinks: &Assets<AudioSink>,
+    song_audios: &mut Assets<SongAudio>,
+    source: &AudioSource,
+    rate: f64,
+    start: f64,
+) {
+    let position = SongPosition::default();
+    let song_audio = song_audios.add(SongAudio {
+        source: source.clone(),
+        position: position.clone(),
+        rate: rate as f32,
+        start,
+    });
+
+    let sink = audio.play(song_audio);
+    commands.insert_resource(SongSink(audio_sinks.get_handle(sink)));
+    commands.insert_resource(position);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn start_song(
+    mut commands: Commands,
+    audio: Res<Audio<SongAudio>>,
+    audio_sinks: Res<Assets<AudioSink>>,
+    audio_sources: Res<Assets<AudioSource>>,
+    mut song_audios: ResMut<Assets<SongAudio>>,
+    time: Res<ControlledTime>,
+    config: Res<SongConfig>,
+    charts: Res<Assets<Chart>>,
+    playing: Option<Res<SongSink>>,
+    seek_events: EventReader<SeekEvent>,
+) {
+    let seeking = !seek_events.is_empty();
+    seek_events.clear();
+
+    // The clock can go back a bit once it follows the song, don't play it twice.
+    // Seeking takes care of playing the song itself
+    if playing.is_some() || seeking {
+        return;
+    }
+
+    // Started once the clock gets to the song, even if it's already past the start on the first frame
+    if time.song_seconds() >= 0. {
+        let source = charts
+            .get(&config.chart)
+            .and_then(|chart| audio_sources.get(&chart.song_audio));
+
+        if let Some(source) = source {
+            play_song(&mut commands, &audio, &audio_sinks, &mut song_audios, source, time.rate(), 0.);
+        }
+    }
+}
+
+/// Plays the song again from a bit before the point seeked to.
+/// If that's before the song starts, `start_song` plays it once the clock gets there
+#[allow(clippy::too_many_arguments)]
+fn seek_song(
+    mut commands: Commands,
+    audio: Res<Audio<SongAudio>>,
+    audio_sinks: Res<Assets<AudioSink>>,
+    audio_sources: Res<Assets<AudioSource>>,
+    mut song_audios: ResMut<Assets<SongAudio>>,
+    time: Res<ControlledTime>,
+    config: Res<SongConfig>,
+    charts: Res<Assets<Chart>>,
+    sink: Option<Res<SongSink>>,
+    mut seek_events: EventReader<SeekEvent>,
+) {
+    let seek = match seek_events.iter().last() {
+        Some(seek) => seek,
+        None => return,
+    };
+
+    if let Some(sink) = sink.and_then(|sink| audio_sinks.get(&sink.0)) {
+        sink.stop();
+    }
+    commands.remove_resource::<SongSink>();
+    commands.remove_resource::<SongPosition>();
+
+    // The lead-in is in real seconds, the song's are scaled by the rate
+    let start = seek.seconds - PRACTICE_LEAD_IN * time.rate();
+    let source = charts
+        .get(&config.chart)
+        .and_then(|chart| audio_sources.get(&chart.song_audio));
+
+    if let Some(source) = source.filter(|_| start > 0.) {
+        play_song(&mut commands, &audio, &audio_sinks, &mut song_audios, source, time.rate(), start);
+    }
+}
+
+fn stop_song(mut commands: Commands, sink: Option<Res<SongSink>>, audio_sinks: Res<Assets<AudioSink>>) {
+    if let Some(sink) = sink.and_then(|sink| audio_sinks.get(&sink.0)) {
+        sink.stop();
+    }
+    commands.remove_resource::<SongSink>();
+    commands.remove_resource::<SongPosition>();
+}
+
+fn pause_song(sink: Option<Res<SongSink>>, audio_sinks: Res<Assets<AudioSink>>) {
+    if let Some(sink) = sink.and_then(|sink| audio_sinks.get(&sink.0)) {
+        sink.pause();
+    }
+}
+
+fn resume_song(sink: Option<Res<SongSink>>, audio_sinks: Res<Assets<AudioSink>>) {
+    if let Some(sink) = sink.and_then(|sink| audio_sinks.get(&sink.0)) {
+        sink.play();
     }
 }
 
 pub struct AudioPlugin;
 impl Plugin for AudioPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(SystemSet::on_update(AppState::Game).with_system(start_song));
+        app.add_asset::<SongAudio>()
+           .init_resource::<Audio<SongAudio>>()
+           .init_non_send_resource::<AudioOutput<SongAudio>>()
+           .add_system_to_stage(CoreStage::PostUpdate, play_queued_audio_system::<SongAudio>)
+           .add_asset::<Metronome>()
+           .init_resource::<Audio<Metronome>>()
+           .init_non_send_resource::<AudioOutput<Metronome>>()
+           .add_system_to_stage(CoreStage::PostUpdate, play_queued_audio_system::<Metronome>)
+           .add_system_set(
+                SystemSet::on_update(AppState::Game)
+                    .with_system(start_song.after(update_time).after(SendSeek))
+                    .with_system(seek_song.after(SendSeek))
+           )
+           .add_system_set(SystemSet::on_pause(AppState::Game).with_system(pause_song))
+           .add_system_set(SystemSet::on_resume(AppState::Game).with_system(resume_song))
+           .add_system_set(SystemSet::on_exit(AppState::Game).with_system(stop_song));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,145 @@
+use bevy::{audio::AudioSink, prelude::*};
+
+use crate::{
+    audio::{Metronome, SongPosition},
+    consts::AppState,
+    settings::{save_settings, GamepadBindings, KeyBindings, Settings},
+};
+
+/// Seconds between metronome clicks
+const CLICK_INTERVAL: f64 = 0.5;
+
+/// Clicks to let by before taps count, so the player can catch the beat
+const WARMUP_CLICKS: f64 = 4.;
+
+/// Taps averaged to get the offset
+const CALIBRATION_TAPS: usize = 16;
+
+/// Metronome being played, along with the offsets of the taps so far in seconds
+struct Calibration {
+    sink: Handle<AudioSink>,
+    position: SongPosition,
+    offsets: Vec<f64>,
+}
+
+impl Calibration {
+    fn average(&self) -> f64 {
+        self.offsets.iter().sum::<f64>() / self.offsets.len().max(1) as f64
+    }
+}
+
+#[derive(Component)]
+struct CalibrationText;
+
+fn setup_calibration(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    audio: Res<Audio<Metronome>>,
+    audio_sinks: Res<Assets<AudioSink>>,
+    mut metronomes: ResMut<Assets<Metronome>>,
+    settings: Res<Settings>,
+) {
+    let position = SongPosition::default();
+    let metronome = metronomes.add(Metronome { interval: CLICK_INTERVAL, position: position.clone() });
+    let sink = audio_sinks.get_handle(audio.play(metronome));
+    commands.insert_resource(Calibration { sink, position, offsets: vec![] });
+
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(
+                format!(
+                    "Press Space on every click\nCurrent offset: {:.0} ms\n\nEscape to go back",
+                    settings.offset
+                ),
+                TextStyle {
+                    font,
+                    font_size: 30.0,
+                    color: Color::rgb(0.9, 0.9, 0.9),
+                },
+            )
+            .with_alignment(TextAlignment::CENTER),
+            ..Default::default()
+        })
+        .insert(CalibrationText);
+}
+
+/// Measures how far each tap is from the closest click, as heard by the player
+fn record_taps(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut calibration: ResMut<Calibration>,
+    mut settings: ResMut<Settings>,
+    keys: Res<KeyBindings>,
+    gamepad: Res<GamepadBindings>,
+    mut query: Query<&mut Text, With<CalibrationText>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::Space) || calibration.offsets.len() >= CALIBRATION_TAPS {
+        return;
+    }
+
+    let seconds = match calibration.position.seconds() {
+        Some(seconds) if seconds >= WARMUP_CLICKS * CLICK_INTERVAL => seconds,
+        _ => return,
+    };
+    let closest_click = (seconds / CLICK_INTERVAL).round() * CLICK_INTERVAL;
+    calibration.offsets.push(seconds - closest_click);
+
+    let average = calibration.average() * 1000.;
+    let message = if calibration.offsets.len() < CALIBRATION_TAPS {
+        format!(
+            "Press Space on every click\nTaps: {}/{}\nAverage offset: {:.0} ms\n\nEscape to go back",
+            calibration.offsets.len(),
+            CALIBRATION_TAPS,
+            average
+        )
+    } else {
+        settings.offset = average.round();
+        save_settings(&settings, &keys, &gamepad);
+        format!("Offset saved: {:.0} ms\n\nEscape to go back", settings.offset)
+    };
+
+    for mut text in query.iter_mut() {
+        text.sections[0].value = message.clone();
+    }
+}
+
+fn leave_calibration(keyboard_input: Res<Input<KeyCode>>, mut state: ResMut<State<AppState>>) {
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        state.set(AppState::Menu).expect("Couldn't switch to state Menu");
+    }
+}
+
+fn tear_down_calibration(
+    mut commands: Commands,
+    calibration: Res<Calibration>,
+    audio_sinks: Res<Assets<AudioSink>>,
+    query: Query<Entity, With<CalibrationText>>,
+) {
+    if let Some(sink) = audio_sinks.get(&calibration.sink) {
+        sink.stop();
+    }
+    commands.remove_resource::<Calibration>();
+
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+pub struct CalibrationPlugin;
+impl Plugin for CalibrationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+                SystemSet::on_enter(AppState::Calibration)
+                    .with_system(setup_calibration)
+           )
+           .add_system_set(
+                SystemSet::on_update(AppState::Calibration)
+                    .with_system(record_taps)
+                    .with_system(leave_calibration)
+           )
+           .add_system_set(
+                SystemSet::on_exit(AppState::Calibration)
+                    .with_system(tear_down_calibration)
+           );
+    }
+}
@@ -0,0 +1,198 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use bevy::{
+    asset::{AssetIoError, AssetLoader, AssetPath, BoxedFuture, LoadContext, LoadState, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+};
+
+use crate::{
+    consts::AppState,
+    types::{parse_chart, ArrowTime, ChartLoadError, LaneLayout, TimingMap},
+};
+
+/// A song chart loaded from a `.toml` file in `assets/songs`
+#[derive(Debug, TypeUuid)]
+#[uuid = "8f5a4c3e-2d6b-4f0e-9a71-3c52e1b7d904"]
+pub struct Chart {
+    pub name: String,
+    pub song_audio: Handle<AudioSource>,
+    /// Seconds between entering the game and the song starting
+    pub lead_in: f64,
+    pub layout: LaneLayout,
+    /// Only charts that declare a `bpm` have one
+    pub timing: Option<TimingMap>,
+    pub arrows: Vec<ArrowTime>,
+}
+
+impl Chart {
+    /// Seconds until the last arrow is done
+    pub fn length(&self) -> f64 {
+        self.arrows
+            .iter()
+            .map(|arrow| arrow.click_time + arrow.hold.unwrap_or(0.))
+            .fold(0., f64::max)
+    }
+}
+
+/// Chart picked from the menu, along with the arrows that haven't spawned yet
+pub struct SongConfig {
+    /// File name of the chart in `assets/songs`, without `.toml`
+    pub song: String,
+    pub chart: Handle<Chart>,
+    pub arrows: Vec<ArrowTime>,
+}
+
+/// Errors from charts that failed to load, kept so they can be shown to the player.
+/// The asset server only logs loader errors, so the loader stores them here too.
+#[derive(Clone, Default)]
+pub struct ChartLoadErrors(Arc<Mutex<HashMap<PathBuf, ChartLoadError>>>);
+
+impl ChartLoadErrors {
+    fn insert(&self, path: &Path, error: ChartLoadError) {
+        self.0.lock().unwrap().insert(path.to_path_buf(), error);
+    }
+
+    fn take(&self, path: &Path) -> Option<ChartLoadError> {
+        self.0.lock().unwrap().remove(path)
+    }
+}
+
+struct ChartLoader {
+    errors: ChartLoadErrors,
+}
+
+impl AssetLoader for ChartLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let file = match parse_chart(bytes) {
+                Ok(file) => file,
+                Err(error) => {
+                    let message = error.to_string();
+                    self.errors.insert(load_context.path(), error);
+                    return Err(bevy::asset::Error::msg(message));
+                }
+            };
+
+            // Audio is named relative to the chart
+            let audio_path = match load_context.path().parent() {
+                Some(parent) => parent.join(&file.filename),
+                None => PathBuf::from(&file.filename),
+            };
+            let audio_asset_path = AssetPath::new(audio_path, None);
+            let song_audio = load_context.get_handle(audio_asset_path.clone());
+
+            // Forget about any earlier failure, it's been fixed
+            self.errors.take(load_context.path());
+
+            let chart = Chart {
+                name: file.name,
+                song_audio,
+                lead_in: file.lead_in,
+                layout: file.layout,
+                timing: file.timing,
+                arrows: file.arrows,
+            };
+            load_context.set_default_asset(LoadedAsset::new(chart).with_dependency(audio_asset_path));
+
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["toml"]
+    }
+}
+
+/// Sent when the chart picked from the menu, or its audio, couldn't be loaded
+pub struct ChartLoadFailed {
+    pub path: PathBuf,
+    pub error: ChartLoadError,
+}
+
+/// Turns a failed load the loader didn't see into an error, usually a missing file
+fn asset_io_error(asset_server: &AssetServer, path: &Path) -> ChartLoadError {
+    match asset_server.asset_io().get_metadata(path) {
+        Err(AssetIoError::NotFound(path)) => ChartLoadError::MissingFile(path),
+        Err(AssetIoError::Io(source)) => ChartLoadError::Io { path: path.to_path_buf(), source },
+        _ => ChartLoadError::Io {
+            path: path.to_path_buf(),
+            source: io::Error::other("the asset server couldn't load it"),
+        },
+    }
+}
+
+/// Waits for the chart and its audio, then starts the game or goes back to the menu
+fn wait_for_chart(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    config: Res<SongConfig>,
+    charts: Res<Assets<Chart>>,
+    errors: Res<ChartLoadErrors>,
+    mut state: ResMut<State<AppState>>,
+    mut failed_events: EventWriter<ChartLoadFailed>,
+) {
+    let chart_path = match asset_server.get_handle_path(&config.chart) {
+        Some(asset_path) => asset_path.path().to_path_buf(),
+        None => return,
+    };
+
+    let error = match asset_server.get_load_state(&config.chart) {
+        LoadState::Failed => errors
+            .take(&chart_path)
+            .unwrap_or_else(|| asset_io_error(&asset_server, &chart_path)),
+        LoadState::Loaded => {
+            let chart = match charts.get(&config.chart) {
+                Some(chart) => chart,
+                None => return,
+            };
+
+            match asset_server.get_load_state(&chart.song_audio) {
+                LoadState::Loaded => {
+                    // Everything that spawns per lane goes by the chart's layout
+                    commands.insert_resource(chart.layout);
+                    state.replace(AppState::Game).expect("Couldn't switch to state Game");
+                    return;
+                }
+                LoadState::Failed => {
+                    let audio_path = asset_server
+                        .get_handle_path(&chart.song_audio)
+                        .map(|asset_path| asset_path.path().to_path_buf())
+                        .unwrap_or_default();
+                    ChartLoadError::MissingAudio(audio_path)
+                }
+                _ => return,
+            }
+        }
+        _ => return,
+    };
+
+    failed_events.send(ChartLoadFailed { path: chart_path, error });
+    state.pop().expect("Couldn't go back to the menu");
+}
+
+pub struct ChartPlugin;
+impl Plugin for ChartPlugin {
+    fn build(&self, app: &mut App) {
+        let errors = ChartLoadErrors::default();
+
+        app.add_asset::<Chart>()
+           .add_asset_loader(ChartLoader { errors: errors.clone() })
+           .insert_resource(errors)
+           .init_resource::<LaneLayout>()
+           .add_event::<ChartLoadFailed>()
+           .add_system_set(
+                SystemSet::on_update(AppState::Loading)
+                    .with_system(wait_for_chart)
+           );
+    }
+}
@@ -1,3 +1,5 @@
+use bevy::prelude::KeyCode;
+
 /// Speed at which a Slow arrow moves
 pub const BASE_SPEED: f32 = 200.;
 
@@ -7,9 +9,65 @@ pub const SPAWN_POSITION: f32 = -400.;
 /// X coordinate value where the arrows shoudl be clicked
 pub const TARGET_POSITION: f32 = 200.;
 
-/// Margin of error for clicking an arrow
+/// Distance past the target at which arrows start falling off the screen.
+/// Judgement goes by time, see `JudgementWindows`
 pub const THRESHOLD: f32 = 20.;
 
+/// Points lost when pressing a mine
+pub const MINE_PENALTY: usize = 50;
+
+/// Seconds between entering the game and the song starting, for charts that don't set `lead_in`
+pub const DEFAULT_LEAD_IN: f64 = 3.;
+
+/// Slowest and fastest the song can be played at, for practice
+pub const MIN_RATE: f64 = 0.5;
+pub const MAX_RATE: f64 = 2.;
+
+/// Step the playback rate goes up or down by in the menu
+pub const RATE_STEP: f64 = 0.1;
+
+/// Real seconds played before the point seeked to in practice mode, for arrows to come in
+pub const PRACTICE_LEAD_IN: f64 = 2.;
+
+/// Keys that change the scroll speed during the song, they can't be bound to a lane
+pub const FASTER_KEYS: [KeyCode; 3] = [KeyCode::Equals, KeyCode::Plus, KeyCode::NumpadAdd];
+pub const SLOWER_KEYS: [KeyCode; 2] = [KeyCode::Minus, KeyCode::NumpadSubtract];
+pub const SCROLL_MODE_KEY: KeyCode = KeyCode::Tab;
+
+/// Keys that seek and loop the song in practice mode, they can't be bound to a lane either.
+/// Jump keys go to that tenth of the song
+pub const SEEK_BACK_KEY: KeyCode = KeyCode::LBracket;
+pub const SEEK_FORWARD_KEY: KeyCode = KeyCode::RBracket;
+pub const JUMP_KEYS: [KeyCode; 10] = [
+    KeyCode::Key0, KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4,
+    KeyCode::Key5, KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
+];
+pub const LOOP_START_KEY: KeyCode = KeyCode::A;
+pub const LOOP_END_KEY: KeyCode = KeyCode::B;
+pub const CLEAR_LOOP_KEY: KeyCode = KeyCode::Back;
+pub const RESTART_KEY: KeyCode = KeyCode::R;
+
+/// Seconds counted down before the song goes on after a pause
+pub const RESUME_COUNTDOWN: f64 = 3.;
+
+/// Health the player starts a song with, health goes from 0 to 1
+pub const START_HEALTH: f32 = 0.5;
+
+/// Health lost when pressing a mine
+pub const MINE_HEALTH_PENALTY: f32 = 0.1;
+
+/// Combo needed for each step of bonus points
+pub const COMBO_BONUS_STEP: usize = 10;
+
+/// Extra points given per combo step, as a fraction of the hit's points
+pub const COMBO_BONUS_PER_STEP: f32 = 0.1;
+
+/// Max extra points from combo, as a fraction of the hit's points. Set to 0 to turn combo bonus off
+pub const MAX_COMBO_BONUS: f32 = 1.;
+
+/// Height of the screen the lanes can take, lanes get closer together when there are many
+pub const LANES_HEIGHT: f32 = 500.;
+
 /// Total distance traveled by an arrow, from spawn to target
 pub const DISTANCE: f32 = TARGET_POSITION - SPAWN_POSITION;
 
@@ -17,6 +75,12 @@ pub const DISTANCE: f32 = TARGET_POSITION - SPAWN_POSITION;
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum AppState {
     Menu,
+    Loading,
     Game,
+    Paused,
+    Failed,
+    Results,
+    Calibration,
+    Controls,
     MakeMap
 }
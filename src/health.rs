@@ -0,0 +1,50 @@
+use bevy::prelude::*;
+
+use crate::{
+    arrows::{CorrectArrowEvent, MineHitEvent, MissedArrowEvent},
+    consts::*,
+    practice::Practice,
+    score::Judgement,
+    shaders::material::Health,
+};
+
+/// Restores health on hits and drains it on misses and mines, failing the song once it runs out.
+/// Songs can't be failed in practice
+fn update_health(
+    mut correct_arrow_events: EventReader<CorrectArrowEvent>,
+    mut missed_arrow_events: EventReader<MissedArrowEvent>,
+    mut mine_hit_events: EventReader<MineHitEvent>,
+    mut query: Query<&mut Health>,
+    practice: Option<Res<Practice>>,
+    mut state: ResMut<State<AppState>>,
+) {
+    let change = correct_arrow_events
+        .iter()
+        .map(|event| event.judgement.health())
+        .chain(missed_arrow_events.iter().map(|_| Judgement::Miss.health()))
+        .chain(mine_hit_events.iter().map(|_| -MINE_HEALTH_PENALTY))
+        .sum::<f32>();
+
+    if change == 0. {
+        return;
+    }
+
+    for mut health in query.iter_mut() {
+        health.value = (health.value + change).clamp(0., 1.);
+
+        if health.value <= 0. && practice.is_none() {
+            state.set(AppState::Failed).expect("Couldn't switch to state Failed");
+            return;
+        }
+    }
+}
+
+pub struct HealthPlugin;
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_update(AppState::Game)
+                .with_system(update_health)
+        );
+    }
+}
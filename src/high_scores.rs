@@ -0,0 +1,134 @@
+use std::{fs, io::ErrorKind};
+
+use bevy::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{consts::RATE_STEP, score::ScoreResource};
+
+/// File the best scores are kept in, next to `settings.toml`
+const HIGH_SCORES_FILE: &str = "scores.toml";
+
+/// Best run of a chart at one playback rate
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HighScore {
+    /// File name of the chart in `assets/songs`, without `.toml`
+    pub chart: String,
+    /// Playback rate of the run, runs at other rates are kept apart
+    pub rate: f64,
+    pub score: usize,
+    pub accuracy: f64,
+    pub max_combo: usize,
+}
+
+impl HighScore {
+    fn is_for(&self, chart: &str, rate: f64) -> bool {
+        self.chart == chart && (self.rate - rate).abs() < RATE_STEP / 2.
+    }
+}
+
+/// Best scores of every chart played, at every rate, kept between sessions
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HighScores {
+    #[serde(default)]
+    scores: Vec<HighScore>,
+}
+
+impl HighScores {
+    pub fn best(&self, chart: &str, rate: f64) -> Option<&HighScore> {
+        self.scores.iter().find(|high_score| high_score.is_for(chart, rate))
+    }
+
+    /// Keeps the run if it beats the best one for this chart and rate, returns whether it did
+    pub fn record(&mut self, chart: &str, rate: f64, score: &ScoreResource) -> bool {
+        let run = HighScore {
+            chart: chart.to_string(),
+            rate,
+            score: score.score(),
+            accuracy: score.accuracy(),
+            max_combo: score.max_combo(),
+        };
+
+        match self.scores.iter_mut().find(|high_score| high_score.is_for(chart, rate)) {
+            Some(best) if best.score >= run.score => false,
+            Some(best) => {
+                *best = run;
+                true
+            }
+            None => {
+                self.scores.push(run);
+                true
+            }
+        }
+    }
+}
+
+/// Reads the high scores file, starting with none if there's no file or it can't be read
+fn load_high_scores() -> HighScores {
+    let text = match fs::read_to_string(HIGH_SCORES_FILE) {
+        Ok(text) => text,
+        Err(error) if error.kind() == ErrorKind::NotFound => return HighScores::default(),
+        Err(error) => {
+            warn!("Couldn't read {}: {}", HIGH_SCORES_FILE, error);
+            return HighScores::default();
+        }
+    };
+
+    toml::from_str(&text).unwrap_or_else(|error| {
+        warn!("Couldn't parse {}: {}", HIGH_SCORES_FILE, error);
+        HighScores::default()
+    })
+}
+
+pub fn save_high_scores(high_scores: &HighScores) {
+    let text = toml::to_string(high_scores).expect("Couldn't convert high scores to toml text");
+    if let Err(error) = fs::write(HIGH_SCORES_FILE, text) {
+        warn!("Couldn't write {}: {}", HIGH_SCORES_FILE, error);
+    }
+}
+
+pub struct HighScoresPlugin;
+impl Plugin for HighScoresPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_high_scores());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::score::Judgement;
+
+    fn score_of(hits: usize) -> ScoreResource {
+        let mut score = ScoreResource::default();
+        for _ in 0..hits {
+            score.increase_correct(Judgement::Perfect);
+        }
+        score
+    }
+
+    #[test]
+    fn record_keeps_the_best_run() {
+        let mut high_scores = HighScores::default();
+
+        assert!(high_scores.record("song", 1., &score_of(2)));
+        assert!(!high_scores.record("song", 1., &score_of(1)));
+        assert!(high_scores.record("song", 1., &score_of(3)));
+
+        assert_eq!(high_scores.best("song", 1.).unwrap().score, score_of(3).score());
+        assert_eq!(high_scores.scores.len(), 1);
+    }
+
+    #[test]
+    fn record_keeps_charts_and_rates_apart() {
+        let mut high_scores = HighScores::default();
+
+        high_scores.record("song", 1., &score_of(3));
+        assert!(high_scores.record("song", 0.5, &score_of(1)));
+        assert!(high_scores.record("other", 1., &score_of(1)));
+
+        assert_eq!(high_scores.best("song", 0.5).unwrap().score, score_of(1).score());
+        assert!(high_scores.best("song", 1.5).is_none());
+        // Rates that only differ by float errors are the same rate
+        assert!(high_scores.best("song", 0.1 * 10.).is_some());
+    }
+}
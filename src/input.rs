@@ -0,0 +1,90 @@
+use bevy::{input::InputSystem, prelude::*};
+
+use crate::{
+    settings::{GamepadBindings, KeyBindings},
+    types::LaneLayout,
+};
+
+/// Which lanes are pressed this frame, from the keyboard and every gamepad
+#[derive(Default)]
+pub struct LaneInput {
+    pressed: Vec<bool>,
+    just_pressed: Vec<bool>,
+}
+
+impl LaneInput {
+    /// Checks if anything bound to this lane is being held
+    pub fn pressed(&self, lane: usize) -> bool {
+        self.pressed.get(lane).copied().unwrap_or(false)
+    }
+
+    /// Checks if this lane started being pressed this frame
+    pub fn just_pressed(&self, lane: usize) -> bool {
+        self.just_pressed.get(lane).copied().unwrap_or(false)
+    }
+}
+
+fn gamepad_pressed(
+    layout: LaneLayout,
+    lane: usize,
+    gamepads: &Gamepads,
+    bindings: &GamepadBindings,
+    buttons: &Input<GamepadButton>,
+    axes: &Axis<GamepadAxis>,
+) -> bool {
+    gamepads.iter().any(|gamepad| {
+        let mapping = bindings.mapping(*gamepad).layout(layout);
+
+        let button_pressed = mapping
+            .buttons(lane)
+            .iter()
+            .any(|button_type| buttons.pressed(GamepadButton::new(*gamepad, *button_type)));
+        let axis_pushed = mapping
+            .axes
+            .iter()
+            .filter(|binding| binding.lane == lane)
+            .any(|binding| {
+                axes.get(GamepadAxis::new(*gamepad, binding.axis))
+                    .is_some_and(|value| binding.pushed(value))
+            });
+
+        button_pressed || axis_pushed
+    })
+}
+
+/// Gathers the keyboard and gamepads into `LaneInput`, before anything reads it
+#[allow(clippy::too_many_arguments)]
+fn update_lane_input(
+    mut lane_input: ResMut<LaneInput>,
+    layout: Res<LaneLayout>,
+    keyboard_input: Res<Input<KeyCode>>,
+    keys: Res<KeyBindings>,
+    gamepads: Res<Gamepads>,
+    gamepad_bindings: Res<GamepadBindings>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+) {
+    let layout = *layout;
+    lane_input.pressed.resize(layout.lanes(), false);
+    lane_input.just_pressed.resize(layout.lanes(), false);
+
+    for lane in layout.iter() {
+        let was_pressed = lane_input.pressed(lane);
+        let pressed = keys.pressed(layout, lane, &keyboard_input)
+            || gamepad_pressed(layout, lane, &gamepads, &gamepad_bindings, &gamepad_buttons, &gamepad_axes);
+
+        // Pressing another key for a lane that's already held counts as a new press
+        let just_pressed = keys.just_pressed(layout, lane, &keyboard_input) || (pressed && !was_pressed);
+
+        lane_input.pressed[lane] = pressed;
+        lane_input.just_pressed[lane] = just_pressed;
+    }
+}
+
+pub struct LaneInputPlugin;
+impl Plugin for LaneInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LaneInput>()
+           .add_system_to_stage(CoreStage::PreUpdate, update_lane_input.after(InputSystem));
+    }
+}
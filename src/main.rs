@@ -1,6 +1,7 @@
-use bevy::{prelude::*, app::AppExit};
+use bevy::{prelude::*, app::AppExit, asset::AssetServerSettings};
 
 mod arrows;
+mod chart;
 mod consts;
 mod types;
 mod ui;
@@ -10,41 +11,59 @@ mod shaders;
 mod menu;
 mod time;
 mod map_maker;
+mod health;
+mod settings;
+mod calibration;
+mod input;
+mod high_scores;
+mod practice;
 
 use audio::AudioPlugin;
+use calibration::CalibrationPlugin;
+use chart::ChartPlugin;
 use consts::AppState;
+use health::HealthPlugin;
+use high_scores::HighScoresPlugin;
+use input::LaneInputPlugin;
 use map_maker::MapMakerPlugin;
 use menu::MenuPlugin;
+use practice::PracticePlugin;
 use shaders::ShadersPlugin;
 use ui::UIPlugin;
 use arrows::ArrowsPlugins;
 use score::ScoreResource;
+use settings::SettingsPlugin;
 use time::TimePlugin;
 
-fn fire_on_exit(mut app_exit_events: EventWriter<AppExit>, input: Res<Input<KeyCode>>) {
-     if input.just_pressed(KeyCode::Escape) {
+/// Escape quits the game, except during a song where it pauses it, and in screens where it goes back
+fn fire_on_exit(
+    mut app_exit_events: EventWriter<AppExit>,
+    input: Res<Input<KeyCode>>,
+    state: Res<State<AppState>>,
+) {
+     let handled_elsewhere = matches!(
+        state.current(),
+        AppState::Game | AppState::Paused | AppState::Calibration | AppState::Controls
+     );
+     if input.just_pressed(KeyCode::Escape) && !handled_elsewhere {
         app_exit_events.send(AppExit);
      }
 }
 
-fn setup_ui_and_config(mut commands: Commands, asset_server: Res<AssetServer>) {
-    let config = types::load_config("test.toml", &asset_server);
+fn setup_camera(mut commands: Commands) {
     let camera = Camera2dBundle::default();
 
     println!("{:?}", &camera.global_transform.translation());
 
     commands
         .spawn_bundle(camera);
-    
-    commands
-        .insert_resource(config);
 }
 
 fn main() {
     App::new()
         .init_resource::<ScoreResource>()
         .insert_resource(Msaa { samples: 4 })
-        .add_startup_system(setup_ui_and_config)
+        .add_startup_system(setup_camera)
         .add_system(fire_on_exit)
         .insert_resource(WindowDescriptor {
             title: "Rhythm!".to_string(),
@@ -52,9 +71,15 @@ fn main() {
             height: 600.,
             ..Default::default()
         })
+        // Lets charts be edited while the game is running
+        .insert_resource(AssetServerSettings {
+            watch_for_changes: true,
+            ..Default::default()
+        })
         // Changed 0.4 -> 0.5
         .add_state(AppState::Menu)
         .add_plugins(DefaultPlugins) // Expands to CorePlugin, InputPlugin, and WindowPlugin
+        .add_plugin(ChartPlugin)
         .add_plugin(ArrowsPlugins)
         .add_plugin(UIPlugin)
         .add_plugin(AudioPlugin)
@@ -62,5 +87,11 @@ fn main() {
         .add_plugin(MenuPlugin)
         .add_plugin(TimePlugin)
         .add_plugin(MapMakerPlugin)
+        .add_plugin(HealthPlugin)
+        .add_plugin(SettingsPlugin)
+        .add_plugin(CalibrationPlugin)
+        .add_plugin(LaneInputPlugin)
+        .add_plugin(HighScoresPlugin)
+        .add_plugin(PracticePlugin)
         .run();
 }
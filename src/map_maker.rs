@@ -1,14 +1,16 @@
 use crate::time::ControlledTime;
 use crate::consts::*;
+use crate::input::LaneInput;
+use crate::settings::Settings;
 use crate::types::{
     ArrowTimeToml,
-    Directions::{self, *},
+    LaneLayout,
+    NoteKind,
     Speed
 };
 use bevy::sprite::MaterialMesh2dBundle;
 use bevy::{
     app::AppExit,
-    input::{keyboard::KeyCode, Input},
     prelude::*
 };
 use serde::Serialize;
@@ -17,21 +19,29 @@ use std::io::prelude::*;
 
 #[derive(Serialize, Debug, Default)]
 struct Presses {
+    lanes: usize,
     arrows: Vec<ArrowTimeToml>,
 }
 
 fn save_key_presses(
     time: Res<ControlledTime>,
-    keyboard_input: Res<Input<KeyCode>>,
+    input: Res<LaneInput>,
+    layout: Res<LaneLayout>,
     mut presses: ResMut<Presses>
 ) {
-    let directions = [Up, Down, Left, Right];
-    for direction in directions.iter() {
-        if direction.key_jest_pressed(&keyboard_input) {
+    presses.lanes = layout.lanes();
+    for lane in layout.iter() {
+        if input.just_pressed(lane) {
             presses.arrows.push(ArrowTimeToml { 
-                click_time: time.seconds_since_startup(),
+                click_time: Some(time.seconds_since_startup()),
+                beat: None,
+                end_time: None,
+                end_beat: None,
+                duration: None,
+                kind: NoteKind::Tap,
                 speed: Speed::Slow,
-                direction: *direction
+                direction: None,
+                lane: Some(lane)
             });
         }
     }
@@ -68,44 +78,40 @@ fn start_song(audio: Res<Audio>, map_maker_audio: Res<MapMakerAudio>) {
 }
 
 #[derive(Component)]
-struct MapMakerArrow(Directions);
+struct MapMakerArrow(usize);
 
 fn setup_map_maker_arrows(
     mut commands: Commands,
     mut materials: ResMut<Assets<ColorMaterial>>,
     mut mesh_assets: ResMut<Assets<Mesh>>,
-    asset_server: ResMut<AssetServer>
+    asset_server: ResMut<AssetServer>,
+    layout: Res<LaneLayout>,
+    settings: Res<Settings>,
 ) {
     let border_handle = materials.add(asset_server.load("images/arrow_border.png").into());
+    let size = 140. * layout.scale();
 
-    let directions = [Up, Down, Left, Right];
-    for direction in directions.iter() {
-        let y = match direction {
-            Up => 150.,
-            Down => 50.,
-            Left => -50.,
-            Right => -150.,
-        };
-
-        let mut transform = Transform::from_translation(Vec3::new(0., y, 1.));
-        transform.rotate(Quat::from_rotation_z(direction.rotation()));
+    for lane in layout.iter() {
+        let position = settings.scroll.place(0., layout.y(lane));
+        let mut transform = Transform::from_translation(position.extend(1.));
+        transform.rotate(Quat::from_rotation_z(layout.rotation(lane, settings.scroll.rotation())));
         commands
             .spawn_bundle(MaterialMesh2dBundle {
                 material: border_handle.clone(),
-                mesh: mesh_assets.add(Mesh::from(shape::Quad::new(Vec2::new(140., 140.)))).into(),
+                mesh: mesh_assets.add(Mesh::from(shape::Quad::new(Vec2::new(size, size)))).into(),
                 transform,
                 ..Default::default()
             })
-            .insert(MapMakerArrow(*direction));
+            .insert(MapMakerArrow(lane));
     }
 }
 
 fn toggle_map_maker_arrows(
     mut query: Query<(&mut Visibility, &MapMakerArrow)>,
-    keyboard_input: Res<Input<KeyCode>>
+    input: Res<LaneInput>,
 ) {
     for (mut visible, arrow) in query.iter_mut() {
-        visible.is_visible = arrow.0.key_pressed(&keyboard_input);
+        visible.is_visible = input.pressed(arrow.0);
     }
 }
 
@@ -1,6 +1,15 @@
-use std::{fs::read_dir, fmt::format};
+use std::path::Path;
 
-use crate::{consts::*, types::load_config};
+use crate::{
+    chart::{Chart, ChartLoadFailed, SongConfig},
+    consts::*,
+    high_scores::{save_high_scores, HighScores},
+    practice::Practice,
+    score::{GradeThresholds, Judgement, ScoreResource},
+    settings::{save_settings, GamepadBindings, KeyBindings, Settings},
+    time::ControlledTime,
+    types::LaneLayout,
+};
 use bevy::prelude::*;
 
 struct ButtonMaterials {
@@ -32,12 +41,17 @@ impl FromWorld for ButtonMaterials {
 #[derive(Component)]
 struct MenuUI;
 
-fn setup_menu(mut commands: Commands, button_materials: Res<ButtonMaterials>) {
-    let mut buttons: Vec<MenuButton> = get_songs()
-        .iter()
-        .map(|name| MenuButton::PlaySong(name.clone()))
-        .collect();
-    buttons.push(MenuButton::MakeMap);
+/// Text used to tell the player why a song couldn't be loaded
+#[derive(Component)]
+struct ChartErrorText;
+
+fn setup_menu(
+    mut commands: Commands,
+    button_materials: Res<ButtonMaterials>,
+    asset_server: Res<AssetServer>,
+) {
+    let songs = get_songs(&asset_server);
+    let buttons = [MenuButton::MakeMap, MenuButton::Calibrate, MenuButton::Controls];
 
     commands.spawn_bundle(
         NodeBundle {
@@ -55,46 +69,527 @@ fn setup_menu(mut commands: Commands, button_materials: Res<ButtonMaterials>) {
     )
     .insert(MenuUI)
     .with_children(|parent| {
-        for button in buttons {
-            parent.spawn_bundle(ButtonBundle {
+        for song in songs {
+            parent.spawn_bundle(NodeBundle {
                 style: Style {
-                    size: Size { width: Val::Px(350.), height: Val::Px(65.0) },
                     margin: UiRect::all(Val::Auto),
-                    justify_content: JustifyContent::Center,
-                    align_items: AlignItems::Center,
                     ..Default::default()
                 },
-                color: button_materials.normal,
+                color: button_materials.none,
                 ..Default::default()
             })
-            .with_children(|parent_button| {
-                parent_button.spawn_bundle(TextBundle {
-                    text: Text::from_section(
-                        button.name(),
-                        TextStyle {
-                            font: button_materials.font.clone(),
-                            font_size: 20.0,
-                            color: Color::rgb(0.9, 0.9, 0.9),
-                        }).with_alignment(TextAlignment::CENTER),
-                    ..Default::default()
-                });
-            })
-            .insert(button);
+            .with_children(|row| {
+                let margin = UiRect::all(Val::Px(5.));
+                spawn_button(row, MenuButton::PlaySong(song.clone()), &button_materials, margin);
+                spawn_sized_button(row, MenuButton::PracticeSong(song), &button_materials, margin, Size::new(Val::Px(150.), Val::Px(65.)));
+            });
+        }
+
+        for button in buttons {
+            spawn_button(parent, button, &button_materials, UiRect::all(Val::Auto));
+        }
+
+        // Playback rate for the next song, clicking the rate itself goes back to normal speed
+        parent.spawn_bundle(NodeBundle {
+            style: Style {
+                margin: UiRect::all(Val::Auto),
+                ..Default::default()
+            },
+            color: button_materials.none,
+            ..Default::default()
+        })
+        .with_children(|row| {
+            let margin = UiRect::all(Val::Px(5.));
+            spawn_sized_button(row, MenuButton::SlowerRate, &button_materials, margin, Size::new(Val::Px(65.), Val::Px(65.)));
+            spawn_sized_button(row, MenuButton::Rate, &button_materials, margin, Size::new(Val::Px(200.), Val::Px(65.)));
+            spawn_sized_button(row, MenuButton::FasterRate, &button_materials, margin, Size::new(Val::Px(65.), Val::Px(65.)));
+        });
+
+        parent.spawn_bundle(TextBundle {
+            style: Style {
+                margin: UiRect::all(Val::Auto),
+                ..Default::default()
+            },
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font: button_materials.font.clone(),
+                    font_size: 20.0,
+                    color: Color::rgb(0.9, 0.3, 0.3),
+                }).with_alignment(TextAlignment::CENTER),
+            ..Default::default()
+        })
+        .insert(ChartErrorText);
+    });
+}
+
+fn spawn_button(
+    parent: &mut ChildBuilder,
+    button: MenuButton,
+    button_materials: &ButtonMaterials,
+    margin: UiRect<Val>,
+) {
+    spawn_sized_button(parent, button, button_materials, margin, Size::new(Val::Px(350.), Val::Px(65.)));
+}
+
+fn spawn_sized_button(
+    parent: &mut ChildBuilder,
+    button: MenuButton,
+    button_materials: &ButtonMaterials,
+    margin: UiRect<Val>,
+    size: Size<Val>,
+) {
+    parent.spawn_bundle(ButtonBundle {
+        style: Style {
+            size,
+            margin,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..Default::default()
+        },
+        color: button_materials.normal,
+        ..Default::default()
+    })
+    .with_children(|parent_button| {
+        parent_button.spawn_bundle(TextBundle {
+            text: Text::from_section(
+                button.name(),
+                TextStyle {
+                    font: button_materials.font.clone(),
+                    font_size: 20.0,
+                    color: Color::rgb(0.9, 0.9, 0.9),
+                }).with_alignment(TextAlignment::CENTER),
+            ..Default::default()
+        });
+    })
+    .insert(button);
+}
+
+/// Darkens the whole window, with its children stacked in the middle
+fn overlay_bundle() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            display: Display::Flex,
+            position_type: PositionType::Absolute,
+            size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+            flex_direction: FlexDirection::ColumnReverse,
+            align_items: AlignItems::Center,
+            justify_content: JustifyContent::Center,
+            ..Default::default()
+        },
+        color: Color::rgba(0., 0., 0., 0.7).into(),
+        ..Default::default()
+    }
+}
+
+/// Replaces the playfield once health runs out, the song has already stopped by then
+fn setup_failed_screen(mut commands: Commands, button_materials: Res<ButtonMaterials>) {
+    commands.spawn_bundle(overlay_bundle())
+    .insert(MenuUI)
+    .with_children(|parent| {
+        parent.spawn_bundle(TextBundle {
+            style: Style {
+                margin: UiRect::all(Val::Px(20.)),
+                ..Default::default()
+            },
+            text: Text::from_section(
+                "Song failed",
+                TextStyle {
+                    font: button_materials.font.clone(),
+                    font_size: 60.0,
+                    color: Color::rgb(0.9, 0.3, 0.3),
+                }).with_alignment(TextAlignment::CENTER),
+            ..Default::default()
+        });
+
+        spawn_button(parent, MenuButton::BackToMenu, &button_materials, UiRect::all(Val::Px(10.)));
+    });
+}
+
+/// Shown once the song is over, with how well it was played
+#[allow(clippy::too_many_arguments)]
+fn setup_results_screen(
+    mut commands: Commands,
+    button_materials: Res<ButtonMaterials>,
+    score: Res<ScoreResource>,
+    thresholds: Res<GradeThresholds>,
+    song_config: Res<SongConfig>,
+    charts: Res<Assets<Chart>>,
+    time: Res<ControlledTime>,
+    mut high_scores: ResMut<HighScores>,
+) {
+    use Judgement::*;
+
+    let name = charts
+        .get(&song_config.chart)
+        .map(|chart| chart.name.clone())
+        .unwrap_or_default();
+
+    // Best scores are kept per rate, slowed down runs don't count for normal speed.
+    // They go by file name, several charts can have the same name
+    let rate = time.rate();
+    let song = &song_config.song;
+    let previous_best = high_scores.best(song, rate).map(|best| best.score);
+    let best = if high_scores.record(song, rate, &score) {
+        save_high_scores(&high_scores);
+        "new best!".to_string()
+    } else {
+        format!("best: {}", previous_best.unwrap_or_default())
+    };
+
+    let judgements = [Perfect, Great, Good, Bad, Miss]
+        .iter()
+        .map(|judgement| format!("{}: {}", judgement.name(), score.judgements(*judgement)))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let accuracy = score.accuracy();
+    let results = format!(
+        "Score: {} ({})\n\n{}\n\nMax combo: {}\nAccuracy: {:.2}%",
+        score.score(),
+        best,
+        judgements,
+        score.max_combo(),
+        accuracy,
+    );
+
+    let text_style = TextStyle {
+        font: button_materials.font.clone(),
+        font_size: 25.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+
+    commands.spawn_bundle(overlay_bundle())
+    .insert(MenuUI)
+    .with_children(|parent| {
+        parent.spawn_bundle(TextBundle {
+            text: Text::from_section(
+                format!("{} at {:.1}x", name, rate),
+                TextStyle { font_size: 40.0, ..text_style.clone() },
+            ).with_alignment(TextAlignment::CENTER),
+            ..Default::default()
+        });
+
+        parent.spawn_bundle(TextBundle {
+            style: Style {
+                margin: UiRect::all(Val::Px(10.)),
+                ..Default::default()
+            },
+            text: Text::from_section(
+                thresholds.grade(accuracy).name(),
+                TextStyle {
+                    font_size: 80.0,
+                    color: Color::rgb(0.9, 0.8, 0.3),
+                    ..text_style.clone()
+                },
+            ).with_alignment(TextAlignment::CENTER),
+            ..Default::default()
+        });
+
+        parent.spawn_bundle(TextBundle {
+            text: Text::from_section(results, text_style.clone())
+                .with_alignment(TextAlignment::CENTER),
+            ..Default::default()
+        });
+
+        parent.spawn_bundle(NodeBundle {
+            style: Style {
+                margin: UiRect::all(Val::Px(10.)),
+                ..Default::default()
+            },
+            color: button_materials.none,
+            ..Default::default()
+        })
+        .with_children(|row| {
+            spawn_button(row, MenuButton::Retry, &button_materials, UiRect::all(Val::Px(10.)));
+            spawn_button(row, MenuButton::BackToMenu, &button_materials, UiRect::all(Val::Px(10.)));
+        });
+    });
+}
+
+/// Title of the pause menu, replaced by the countdown once resuming
+#[derive(Component)]
+struct PauseText;
+
+/// Time at which the player asked to resume, the song goes on once the countdown is over
+#[derive(Default)]
+struct ResumeCountdown {
+    started_at: Option<f64>,
+}
+
+fn setup_pause_screen(
+    mut commands: Commands,
+    button_materials: Res<ButtonMaterials>,
+    mut countdown: ResMut<ResumeCountdown>,
+) {
+    countdown.started_at = None;
+
+    commands.spawn_bundle(overlay_bundle())
+    .insert(MenuUI)
+    .with_children(|parent| {
+        parent.spawn_bundle(TextBundle {
+            style: Style {
+                margin: UiRect::all(Val::Px(20.)),
+                ..Default::default()
+            },
+            text: Text::from_section(
+                "Paused",
+                TextStyle {
+                    font: button_materials.font.clone(),
+                    font_size: 60.0,
+                    color: Color::rgb(0.9, 0.9, 0.9),
+                }).with_alignment(TextAlignment::CENTER),
+            ..Default::default()
+        })
+        .insert(PauseText);
+
+        for button in [MenuButton::Resume, MenuButton::Retry, MenuButton::BackToMenu] {
+            spawn_button(parent, button, &button_materials, UiRect::all(Val::Px(10.)));
         }
     });
 }
 
+/// Escape pauses the song, and resumes it once paused.
+/// Runs in every state so the press that pauses can't resume right away.
+fn pause_key_system(
+    input: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    mut countdown: ResMut<ResumeCountdown>,
+    mut state: ResMut<State<AppState>>,
+) {
+    if !input.just_pressed(KeyCode::Escape) {
+        return;
+    }
+
+    match state.current() {
+        AppState::Game => state.push(AppState::Paused).expect("Couldn't switch to state Paused"),
+        AppState::Paused => {
+            countdown.started_at.get_or_insert(time.seconds_since_startup());
+        }
+        _ => {}
+    }
+}
+
+/// Counts down before going back to the song, so the player can get ready
+fn resume_countdown(
+    time: Res<Time>,
+    countdown: Res<ResumeCountdown>,
+    mut query: Query<&mut Text, With<PauseText>>,
+    mut state: ResMut<State<AppState>>,
+) {
+    let started_at = match countdown.started_at {
+        Some(started_at) => started_at,
+        None => return,
+    };
+
+    let left = RESUME_COUNTDOWN - (time.seconds_since_startup() - started_at);
+    if left <= 0. {
+        state.pop().expect("Couldn't go back to the song");
+        return;
+    }
+
+    for mut text in query.iter_mut() {
+        text.sections[0].value = format!("{}", left.ceil());
+    }
+}
+
+/// Buttons clicked or hovered this frame
+type ButtonPresses<'w, 's> =
+    Query<'w, 's, (&'static Interaction, &'static MenuButton), (Changed<Interaction>, With<Button>)>;
+
+/// Layout whose keys are shown, and the lane waiting for a key to be pressed to bind it
+#[derive(Default)]
+struct Rebinding {
+    layout: LaneLayout,
+    lane: Option<usize>,
+}
+
+fn setup_controls_screen(
+    mut commands: Commands,
+    button_materials: Res<ButtonMaterials>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    rebinding.lane = None;
+
+    commands.spawn_bundle(overlay_bundle())
+    .insert(MenuUI)
+    .with_children(|parent| {
+        parent.spawn_bundle(TextBundle {
+            style: Style {
+                margin: UiRect::all(Val::Px(20.)),
+                ..Default::default()
+            },
+            text: Text::from_section(
+                "Controls",
+                TextStyle {
+                    font: button_materials.font.clone(),
+                    font_size: 60.0,
+                    color: Color::rgb(0.9, 0.9, 0.9),
+                }).with_alignment(TextAlignment::CENTER),
+            ..Default::default()
+        });
+
+        spawn_button(parent, MenuButton::Scroll, &button_materials, UiRect::all(Val::Px(5.)));
+        spawn_button(parent, MenuButton::ControlsLayout, &button_materials, UiRect::all(Val::Px(5.)));
+
+        // One button per lane, the ones past the layout's last lane are hidden
+        parent.spawn_bundle(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Row,
+                ..Default::default()
+            },
+            color: button_materials.none,
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            for lane in 0..LaneLayout::MAX_LANES {
+                spawn_sized_button(
+                    parent,
+                    MenuButton::Rebind(lane),
+                    &button_materials,
+                    UiRect::all(Val::Px(5.)),
+                    Size::new(Val::Px(100.), Val::Px(65.)),
+                );
+            }
+        });
+        spawn_button(parent, MenuButton::ResetKeys, &button_materials, UiRect::all(Val::Px(5.)));
+        spawn_button(parent, MenuButton::BackToMenu, &button_materials, UiRect::all(Val::Px(5.)));
+    });
+}
+
+fn controls_button_press_system(
+    query: ButtonPresses,
+    mut rebinding: ResMut<Rebinding>,
+    mut keys: ResMut<KeyBindings>,
+    gamepad: Res<GamepadBindings>,
+    mut settings: ResMut<Settings>,
+) {
+    for (interaction, button) in query.iter() {
+        if *interaction == Interaction::Clicked {
+            match button {
+                MenuButton::Scroll => {
+                    settings.scroll = settings.scroll.next();
+                    save_settings(&settings, &keys, &gamepad);
+                }
+                MenuButton::ControlsLayout => {
+                    // Cycles through the layouts, back to the smallest after the biggest
+                    let lanes = rebinding.layout.lanes() + 1;
+                    rebinding.layout = LaneLayout::new(lanes)
+                        .unwrap_or_else(|| LaneLayout::new(LaneLayout::MIN_LANES).unwrap());
+                    rebinding.lane = None;
+                }
+                MenuButton::Rebind(lane) => rebinding.lane = Some(*lane),
+                MenuButton::ResetKeys => {
+                    *keys = KeyBindings::default();
+                    save_settings(&settings, &keys, &gamepad);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Checks if a key does something else during the song, changing the scroll speed or practicing
+fn is_reserved(key: KeyCode) -> bool {
+    let practice_keys = [SEEK_BACK_KEY, SEEK_FORWARD_KEY, LOOP_START_KEY, LOOP_END_KEY, CLEAR_LOOP_KEY, RESTART_KEY];
+
+    FASTER_KEYS.contains(&key)
+        || SLOWER_KEYS.contains(&key)
+        || key == SCROLL_MODE_KEY
+        || practice_keys.contains(&key)
+        || JUMP_KEYS.contains(&key)
+}
+
+/// Binds the next key pressed to the lane picked, Escape cancels or goes back to the menu
+fn capture_rebind_key(
+    input: Res<Input<KeyCode>>,
+    mut rebinding: ResMut<Rebinding>,
+    mut keys: ResMut<KeyBindings>,
+    gamepad: Res<GamepadBindings>,
+    settings: Res<Settings>,
+    mut state: ResMut<State<AppState>>,
+) {
+    let key = match input.get_just_pressed().next() {
+        Some(key) => *key,
+        None => return,
+    };
+
+    // Keys used during the song would press the lane too, the lane keeps waiting for another key
+    if rebinding.lane.is_some() && is_reserved(key) {
+        return;
+    }
+
+    match rebinding.lane.take() {
+        Some(_) if key == KeyCode::Escape => {}
+        Some(lane) => {
+            keys.bind(rebinding.layout, lane, key);
+            save_settings(&settings, &keys, &gamepad);
+        }
+        None if key == KeyCode::Escape => {
+            state.set(AppState::Menu).expect("Couldn't switch state to Menu");
+        }
+        None => {}
+    }
+}
+
+/// Shows the scroll direction, the layout picked, and the keys bound to each of its lanes on their buttons
+fn update_binding_labels(
+    keys: Res<KeyBindings>,
+    settings: Res<Settings>,
+    rebinding: Res<Rebinding>,
+    mut buttons: Query<(&MenuButton, &Children, &mut Style)>,
+    mut texts: Query<&mut Text>,
+) {
+    if !keys.is_changed() && !settings.is_changed() && !rebinding.is_changed() {
+        return;
+    }
+
+    let layout = rebinding.layout;
+    for (button, children, mut style) in buttons.iter_mut() {
+        let label = match button {
+            MenuButton::Scroll => format!("Scroll: {:?}", settings.scroll),
+            MenuButton::ControlsLayout => format!("Layout: {} lanes", layout.lanes()),
+            MenuButton::Rebind(lane) => {
+                let lane = *lane;
+                style.display = if lane < layout.lanes() { Display::Flex } else { Display::None };
+
+                if rebinding.lane == Some(lane) {
+                    format!("{}\npress a key", layout.lane_name(lane))
+                } else {
+                    let bound = keys
+                        .keys(layout, lane)
+                        .iter()
+                        .map(|key| format!("{:?}", key))
+                        .collect::<Vec<_>>();
+                    format!("{}\n{}", layout.lane_name(lane), bound.join(", "))
+                }
+            }
+            _ => continue,
+        };
+
+        for child in children.iter() {
+            if let Ok(mut text) = texts.get_mut(*child) {
+                text.sections[0].value = label.clone();
+            }
+        }
+    }
+}
+
 fn tear_down_menu(mut commands: Commands, query: Query<(Entity, &MenuUI)>) {
     for (entity, _) in query.iter() {
         commands.entity(entity).despawn_recursive();
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn button_press_system(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    query: Query<(&Interaction, &MenuButton), (Changed<Interaction>, With<Button>)>,
+    query: ButtonPresses,
+    mut error_text: Query<&mut Text, With<ChartErrorText>>,
     mut state: ResMut<State<AppState>>,
+    time: Res<Time>,
+    mut controlled_time: ResMut<ControlledTime>,
+    mut countdown: ResMut<ResumeCountdown>,
 ) {
     for (interaction, button) in query.iter() {
         if *interaction == Interaction::Clicked {
@@ -102,17 +597,89 @@ fn button_press_system(
                 MenuButton::MakeMap => state
                     .set(AppState::MakeMap)
                     .expect("Couldn't switch state to MakeMap"),
-                MenuButton::PlaySong(song) => {
-                    let config = load_config(&*format!("{}.toml", song), &asset_server);
-                    commands.insert_resource(config);
-                    state.set(AppState::Game)
-                         .expect("Couldn't switch to state Game");
+                MenuButton::Calibrate => state
+                    .set(AppState::Calibration)
+                    .expect("Couldn't switch state to Calibration"),
+                MenuButton::Controls => state
+                    .set(AppState::Controls)
+                    .expect("Couldn't switch state to Controls"),
+                MenuButton::SlowerRate => {
+                    let rate = controlled_time.rate() - RATE_STEP;
+                    controlled_time.set_rate(rate);
+                }
+                MenuButton::Rate => controlled_time.set_rate(1.),
+                MenuButton::FasterRate => {
+                    let rate = controlled_time.rate() + RATE_STEP;
+                    controlled_time.set_rate(rate);
+                }
+                // Handled by controls_button_press_system
+                MenuButton::Scroll | MenuButton::ControlsLayout | MenuButton::Rebind(_) | MenuButton::ResetKeys => {}
+                MenuButton::Resume => {
+                    countdown.started_at.get_or_insert(time.seconds_since_startup());
+                }
+                // Replacing also leaves the song when it's paused underneath
+                MenuButton::Retry => state
+                    .replace(AppState::Game)
+                    .expect("Couldn't switch to state Game"),
+                MenuButton::BackToMenu => state
+                    .replace(AppState::Menu)
+                    .expect("Couldn't switch state to Menu"),
+                MenuButton::PlaySong(song) | MenuButton::PracticeSong(song) => {
+                    let chart = asset_server.load(&format!("songs/{}.toml", song));
+                    commands.insert_resource(SongConfig { song: song.clone(), chart, arrows: vec![] });
+
+                    if let MenuButton::PracticeSong(_) = button {
+                        commands.insert_resource(Practice::default());
+                    } else {
+                        commands.remove_resource::<Practice>();
+                    }
+
+                    // Keep the menu around while loading, in case the chart is broken
+                    for mut text in error_text.iter_mut() {
+                        text.sections[0].value.clear();
+                    }
+                    state.push(AppState::Loading)
+                         .expect("Couldn't switch to state Loading");
                 }
             };
         }
     }
 }
 
+/// Shows the playback rate picked for the next song
+fn update_rate_label(
+    time: Res<ControlledTime>,
+    buttons: Query<(&MenuButton, &Children)>,
+    mut texts: Query<&mut Text>,
+) {
+    if !time.is_changed() {
+        return;
+    }
+
+    for (button, children) in buttons.iter() {
+        if let MenuButton::Rate = button {
+            for child in children.iter() {
+                if let Ok(mut text) = texts.get_mut(*child) {
+                    text.sections[0].value = format!("Rate: {:.1}x", time.rate());
+                }
+            }
+        }
+    }
+}
+
+/// Stays in the menu and lets the player pick another song
+fn show_chart_errors(
+    mut failed_events: EventReader<ChartLoadFailed>,
+    mut error_text: Query<&mut Text, With<ChartErrorText>>,
+) {
+    for event in failed_events.iter() {
+        let song = event.path.file_stem().unwrap_or_default().to_string_lossy();
+        for mut text in error_text.iter_mut() {
+            text.sections[0].value = format!("{}: {}", song, event.error);
+        }
+    }
+}
+
 fn button_color_system(
     button_materials: Res<ButtonMaterials>,
     mut query: Query<
@@ -135,17 +702,17 @@ fn button_color_system(
     }
 }
 
-pub fn get_songs() -> Vec<String> {
-    let paths = read_dir("assets/songs").unwrap();
+pub fn get_songs(asset_server: &AssetServer) -> Vec<String> {
+    let paths = asset_server
+        .asset_io()
+        .read_directory(Path::new("songs"))
+        .expect("Couldn't read songs folder");
 
     let mut vec = vec![];
     for path in paths {
-        let path = path.unwrap().path();
-
-        if "toml" == path.as_path().extension().unwrap() {
+        if path.extension().is_some_and(|extension| extension == "toml") {
             vec.push(
-                path.as_path()
-                    .file_stem()
+                path.file_stem()
                     .unwrap()
                     .to_str()
                     .unwrap()
@@ -159,14 +726,40 @@ pub fn get_songs() -> Vec<String> {
 #[derive(Component)]
 enum MenuButton {
     MakeMap,
+    Calibrate,
+    Controls,
+    Scroll,
+    ControlsLayout,
+    Rebind(usize),
+    ResetKeys,
     PlaySong(String),
+    PracticeSong(String),
+    SlowerRate,
+    Rate,
+    FasterRate,
+    Resume,
+    Retry,
+    BackToMenu,
 }
 
 impl MenuButton {
     fn name(&self) -> String {
         match self {
             Self::MakeMap => "Make map".to_string(),
+            Self::Calibrate => "Calibrate offset".to_string(),
+            Self::Controls => "Controls".to_string(),
+            Self::Scroll => "Scroll".to_string(),
+            Self::ControlsLayout => "Layout".to_string(),
+            Self::Rebind(lane) => format!("Lane {}", lane + 1),
+            Self::ResetKeys => "Reset to defaults".to_string(),
             Self::PlaySong(song) => format!("Play song: {}", song),
+            Self::PracticeSong(_) => "Practice".to_string(),
+            Self::SlowerRate => "-".to_string(),
+            Self::Rate => "Rate: 1.0x".to_string(),
+            Self::FasterRate => "+".to_string(),
+            Self::Resume => "Resume".to_string(),
+            Self::Retry => "Retry".to_string(),
+            Self::BackToMenu => "Back to menu".to_string(),
         }
     }
 }
@@ -175,6 +768,8 @@ pub struct MenuPlugin;
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<ButtonMaterials>()
+           .init_resource::<ResumeCountdown>()
+           .init_resource::<Rebinding>()
            .add_system_set(
                 SystemSet::on_enter(AppState::Menu)
                     .with_system(setup_menu)
@@ -183,10 +778,69 @@ impl Plugin for MenuPlugin {
                 SystemSet::on_update(AppState::Menu)
                     .with_system(button_color_system)
                     .with_system(button_press_system)
+                    .with_system(show_chart_errors)
+                    .with_system(update_rate_label)
            )
            .add_system_set(
                 SystemSet::on_exit(AppState::Menu)
                     .with_system(tear_down_menu)
+           )
+           .add_system(pause_key_system)
+           .add_system_set(
+                SystemSet::on_enter(AppState::Paused)
+                    .with_system(setup_pause_screen)
+           )
+           .add_system_set(
+                SystemSet::on_update(AppState::Paused)
+                    .with_system(button_color_system)
+                    .with_system(button_press_system)
+                    .with_system(resume_countdown)
+           )
+           .add_system_set(
+                SystemSet::on_exit(AppState::Paused)
+                    .with_system(tear_down_menu)
+           )
+           .add_system_set(
+                SystemSet::on_enter(AppState::Controls)
+                    .with_system(setup_controls_screen)
+           )
+           .add_system_set(
+                SystemSet::on_update(AppState::Controls)
+                    .with_system(button_color_system)
+                    .with_system(button_press_system)
+                    .with_system(controls_button_press_system)
+                    .with_system(capture_rebind_key)
+                    .with_system(update_binding_labels)
+           )
+           .add_system_set(
+                SystemSet::on_exit(AppState::Controls)
+                    .with_system(tear_down_menu)
+           )
+           .add_system_set(
+                SystemSet::on_enter(AppState::Failed)
+                    .with_system(setup_failed_screen)
+           )
+           .add_system_set(
+                SystemSet::on_update(AppState::Failed)
+                    .with_system(button_color_system)
+                    .with_system(button_press_system)
+           )
+           .add_system_set(
+                SystemSet::on_exit(AppState::Failed)
+                    .with_system(tear_down_menu)
+           )
+           .add_system_set(
+                SystemSet::on_enter(AppState::Results)
+                    .with_system(setup_results_screen)
+           )
+           .add_system_set(
+                SystemSet::on_update(AppState::Results)
+                    .with_system(button_color_system)
+                    .with_system(button_press_system)
+           )
+           .add_system_set(
+                SystemSet::on_exit(AppState::Results)
+                    .with_system(tear_down_menu)
            );
     }
 }
\ No newline at end of file
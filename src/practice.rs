@@ -0,0 +1,175 @@
+use bevy::prelude::*;
+
+use crate::{
+    chart::{Chart, SongConfig},
+    consts::*,
+    time::ControlledTime,
+};
+
+/// Seconds `[` and `]` seek by, or by holding Shift
+const SEEK_STEP: f64 = 5.;
+const SMALL_SEEK_STEP: f64 = 1.;
+
+/// Present while a song is played in practice mode, where it can be seeked through and looped.
+/// Loop points are in seconds into the chart, whatever the playback rate
+#[derive(Default)]
+pub struct Practice {
+    pub loop_start: Option<f64>,
+    pub loop_end: Option<f64>,
+}
+
+impl Practice {
+    /// Where the song goes back to when restarting the section
+    pub fn restart_point(&self) -> f64 {
+        self.loop_start.unwrap_or(0.)
+    }
+}
+
+/// Label of the systems that send `SeekEvent`s, the ones reading them go after it
+/// so a seek is handled all in the same frame
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SendSeek;
+
+/// Sent to jump to `seconds` into the chart. Arrows from there on are brought back,
+/// and the song plays from a bit before so they have time to come in
+pub struct SeekEvent {
+    pub seconds: f64,
+}
+
+/// Seeks, sets the loop points and restarts the section from the keyboard, and loops the section
+///
+/// - `[` and `]` seek back and forward, by less while holding Shift
+/// - `0` to `9` jump to that tenth of the song
+/// - `A` and `B` set the loop start and end where the song is, Backspace clears them
+/// - `R` restarts the section
+pub fn practice_controls(
+    input: Res<Input<KeyCode>>,
+    time: Res<ControlledTime>,
+    song_config: Res<SongConfig>,
+    charts: Res<Assets<Chart>>,
+    practice: Option<ResMut<Practice>>,
+    mut seek_events: EventWriter<SeekEvent>,
+) {
+    let (mut practice, chart) = match (practice, charts.get(&song_config.chart)) {
+        (Some(practice), Some(chart)) => (practice, chart),
+        _ => return,
+    };
+
+    let secs = time.chart_seconds();
+    let length = chart.length();
+
+    let step = if input.any_pressed([KeyCode::LShift, KeyCode::RShift]) { SMALL_SEEK_STEP } else { SEEK_STEP };
+
+    let mut seek_to = None;
+    if input.just_pressed(SEEK_BACK_KEY) {
+        seek_to = Some(secs - step);
+    } else if input.just_pressed(SEEK_FORWARD_KEY) {
+        seek_to = Some(secs + step);
+    } else if let Some(tenth) = JUMP_KEYS.iter().position(|key| input.just_pressed(*key)) {
+        seek_to = Some(length * tenth as f64 / 10.);
+    } else if input.just_pressed(RESTART_KEY) {
+        seek_to = Some(practice.restart_point());
+    }
+
+    if input.just_pressed(LOOP_START_KEY) {
+        let start = secs.max(0.);
+        practice.loop_start = Some(start);
+        if practice.loop_end.is_some_and(|end| end <= start) {
+            practice.loop_end = None;
+        }
+    }
+    if input.just_pressed(LOOP_END_KEY) && secs > practice.restart_point() {
+        practice.loop_end = Some(secs);
+    }
+    if input.just_pressed(CLEAR_LOOP_KEY) {
+        practice.loop_start = None;
+        practice.loop_end = None;
+    }
+
+    // Back to the start of the section once the end is reached
+    if seek_to.is_none() && practice.loop_end.is_some_and(|end| secs >= end) {
+        seek_to = Some(practice.restart_point());
+    }
+
+    if let Some(seconds) = seek_to {
+        seek_events.send(SeekEvent { seconds: seconds.clamp(0., length) });
+    }
+}
+
+#[derive(Component)]
+struct PracticeText;
+
+fn setup_practice_text(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    practice: Option<Res<Practice>>,
+) {
+    if practice.is_none() {
+        return;
+    }
+
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                    font_size: 20.0,
+                    color: Color::rgb(0.8, 0.8, 0.8),
+                },
+            )
+            // Under the time and speed
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(10.),
+                    top: Val::Px(90.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+        )
+        .insert(PracticeText);
+}
+
+fn update_practice_text(practice: Option<Res<Practice>>, mut query: Query<&mut Text, With<PracticeText>>) {
+    let practice = match practice {
+        Some(practice) => practice,
+        None => return,
+    };
+
+    let point = |seconds: Option<f64>| seconds.map_or("-".to_string(), |seconds| format!("{:.1}s", seconds));
+    for mut text in query.iter_mut() {
+        text.sections[0].value = format!(
+            "Practice. Loop {} to {}\n[ ] seek, 0-9 jump, A B loop, Backspace clear, R restart",
+            point(practice.loop_start),
+            point(practice.loop_end),
+        );
+    }
+}
+
+fn tear_down_practice_text(mut commands: Commands, query: Query<Entity, With<PracticeText>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+pub struct PracticePlugin;
+impl Plugin for PracticePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SeekEvent>()
+           .add_system_set(
+                SystemSet::on_enter(AppState::Game)
+                    .with_system(setup_practice_text)
+           )
+           .add_system_set(
+                SystemSet::on_update(AppState::Game)
+                    .with_system(practice_controls.label(SendSeek))
+                    .with_system(update_practice_text)
+           )
+           .add_system_set(
+                SystemSet::on_exit(AppState::Game)
+                    .with_system(tear_down_practice_text)
+           );
+    }
+}
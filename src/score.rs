@@ -1,30 +1,251 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
 use crate::consts::*;
 
+/// How accurate a press was, from best to worst
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Judgement {
+    Perfect,
+    Great,
+    Good,
+    Bad,
+    Miss,
+}
+
+impl Judgement {
+    /// Points given for a press with this judgement
+    pub fn points(&self) -> usize {
+        match self {
+            Judgement::Perfect => 100,
+            Judgement::Great => 75,
+            Judgement::Good => 50,
+            Judgement::Bad => 10,
+            Judgement::Miss => 0,
+        }
+    }
+
+    /// How much a press with this judgement counts towards accuracy, from 0 to 1
+    pub fn weight(&self) -> f64 {
+        match self {
+            Judgement::Perfect => 1.,
+            Judgement::Great => 0.8,
+            Judgement::Good => 0.5,
+            Judgement::Bad => 0.2,
+            Judgement::Miss => 0.,
+        }
+    }
+
+    /// Health gained, or lost when negative, for a press with this judgement
+    pub fn health(&self) -> f32 {
+        match self {
+            Judgement::Perfect => 0.02,
+            Judgement::Great => 0.015,
+            Judgement::Good => 0.005,
+            Judgement::Bad => -0.02,
+            Judgement::Miss => -0.08,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Judgement::Perfect => "Perfect",
+            Judgement::Great => "Great",
+            Judgement::Good => "Good",
+            Judgement::Bad => "Bad",
+            Judgement::Miss => "Miss",
+        }
+    }
+}
+
+/// How far from the target, in milliseconds either way, a press can be to get each judgement.
+/// Presses further away than `bad` don't count at all.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JudgementWindows {
+    pub perfect: f64,
+    pub great: f64,
+    pub good: f64,
+    pub bad: f64,
+}
+
+impl Default for JudgementWindows {
+    fn default() -> Self {
+        Self { perfect: 22.5, great: 45., good: 90., bad: 135. }
+    }
+}
+
+impl JudgementWindows {
+    /// Windows read from the settings file, back to the defaults unless every one is
+    /// a positive number and each is wider than the one before
+    pub fn checked(self) -> Self {
+        let windows = [self.perfect, self.great, self.good, self.bad];
+        let positive = windows.iter().all(|window| window.is_finite() && *window > 0.);
+        let ascending = windows.windows(2).all(|pair| pair[0] < pair[1]);
+
+        if positive && ascending {
+            self
+        } else {
+            warn!("Judgement windows {:?} aren't positive and ascending, using the defaults", windows);
+            Self::default()
+        }
+    }
+
+    /// Judges a press `offset` seconds away from the target, `None` if it's outside every window
+    pub fn judge(&self, offset: f64) -> Option<Judgement> {
+        let millis = offset.abs() * 1000.;
+
+        if millis <= self.perfect {
+            Some(Judgement::Perfect)
+        } else if millis <= self.great {
+            Some(Judgement::Great)
+        } else if millis <= self.good {
+            Some(Judgement::Good)
+        } else if millis <= self.bad {
+            Some(Judgement::Bad)
+        } else {
+            None
+        }
+    }
+
+    /// Seconds either way from the target in which presses count
+    pub fn hit_window(&self) -> f64 {
+        self.bad / 1000.
+    }
+}
+
+/// Letter grade given according to accuracy
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Grade {
+    SS,
+    S,
+    A,
+    B,
+    C,
+    D,
+    F,
+}
+
+impl Grade {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Grade::SS => "SS",
+            Grade::S => "S",
+            Grade::A => "A",
+            Grade::B => "B",
+            Grade::C => "C",
+            Grade::D => "D",
+            Grade::F => "F",
+        }
+    }
+}
+
+/// Minimum accuracy percentage needed for each grade, anything below `d` is an F
+pub struct GradeThresholds {
+    pub ss: f64,
+    pub s: f64,
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    pub d: f64,
+}
+
+impl Default for GradeThresholds {
+    fn default() -> Self {
+        Self { ss: 100., s: 95., a: 90., b: 80., c: 70., d: 60. }
+    }
+}
+
+impl GradeThresholds {
+    pub fn grade(&self, accuracy: f64) -> Grade {
+        if accuracy >= self.ss {
+            Grade::SS
+        } else if accuracy >= self.s {
+            Grade::S
+        } else if accuracy >= self.a {
+            Grade::A
+        } else if accuracy >= self.b {
+            Grade::B
+        } else if accuracy >= self.c {
+            Grade::C
+        } else if accuracy >= self.d {
+            Grade::D
+        } else {
+            Grade::F
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct ScoreResource {
     corrects: usize,
     fails: usize,
+    holds: usize,
+    dropped_holds: usize,
+    mines_hit: usize,
+    judgements: HashMap<Judgement, usize>,
+    combo: usize,
+    max_combo: usize,
 
     score: usize,
 }
 
 impl ScoreResource {
-    /// Increases number of corrects and adds to score
-    pub fn increase_correct(&mut self, distance: f32) -> usize {
-        self.corrects += 1;
+    fn count_judgement(&mut self, judgement: Judgement) {
+        *self.judgements.entry(judgement).or_default() += 1;
+    }
 
-        // Get a value from 0 to 1 according to how close the press was
-        let score_multiplier = (THRESHOLD - distance.abs()) / THRESHOLD;
-        // Give at least 10 points and 100 at max
-        let points = (score_multiplier * 100.).min(100.).max(10.) as usize;
+    /// Adds the points for a hit, with the bonus for the current combo, then extends the combo
+    fn add_hit_points(&mut self, judgement: Judgement) -> usize {
+        let steps = (self.combo / COMBO_BONUS_STEP) as f32;
+        let bonus = (steps * COMBO_BONUS_PER_STEP).min(MAX_COMBO_BONUS);
+        let points = (judgement.points() as f32 * (1. + bonus)) as usize;
         self.score += points;
 
+        self.combo += 1;
+        self.max_combo = self.max_combo.max(self.combo);
+
         points
     }
 
-    /// Increases number of failures
+    /// Increases number of corrects and adds to score
+    pub fn increase_correct(&mut self, judgement: Judgement) -> usize {
+        self.corrects += 1;
+        self.count_judgement(judgement);
+
+        self.add_hit_points(judgement)
+    }
+
+    /// Increases number of failures and breaks the combo
     pub fn increase_fails(&mut self) {
         self.fails += 1;
+        self.count_judgement(Judgement::Miss);
+        self.combo = 0;
+    }
+
+    /// Increases number of completed holds and adds to score according to how
+    /// close to the end of the hold the key was let go
+    pub fn increase_holds(&mut self, judgement: Judgement) -> usize {
+        self.holds += 1;
+        self.count_judgement(judgement);
+
+        self.add_hit_points(judgement)
+    }
+
+    /// Increases number of holds let go too early and breaks the combo
+    pub fn increase_dropped_holds(&mut self) {
+        self.dropped_holds += 1;
+        self.count_judgement(Judgement::Miss);
+        self.combo = 0;
+    }
+
+    /// Increases number of mines pressed, takes points away from score and breaks the combo
+    pub fn increase_mines_hit(&mut self) {
+        self.mines_hit += 1;
+        self.score = self.score.saturating_sub(MINE_PENALTY);
+        self.combo = 0;
     }
 
     // Getters
@@ -39,4 +260,45 @@ impl ScoreResource {
     pub fn fails(&self) -> usize {
         self.fails
     }
-}
\ No newline at end of file
+
+    pub fn holds(&self) -> usize {
+        self.holds
+    }
+
+    pub fn dropped_holds(&self) -> usize {
+        self.dropped_holds
+    }
+
+    pub fn mines_hit(&self) -> usize {
+        self.mines_hit
+    }
+
+    pub fn combo(&self) -> usize {
+        self.combo
+    }
+
+    pub fn max_combo(&self) -> usize {
+        self.max_combo
+    }
+
+    /// Number of presses and releases that got `judgement`
+    pub fn judgements(&self, judgement: Judgement) -> usize {
+        self.judgements.get(&judgement).copied().unwrap_or(0)
+    }
+
+    /// Percentage from 0 to 100 of how accurately notes were hit, weighted by judgement.
+    /// Mines pressed count as missed notes. Starts at 100 before anything is judged.
+    pub fn accuracy(&self) -> f64 {
+        let judged = self.judgements.values().sum::<usize>() + self.mines_hit;
+        if judged == 0 {
+            return 100.;
+        }
+
+        let weighted = self
+            .judgements
+            .iter()
+            .map(|(judgement, count)| judgement.weight() * *count as f64)
+            .sum::<f64>();
+        weighted / judged as f64 * 100.
+    }
+}
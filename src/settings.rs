@@ -0,0 +1,577 @@
+use std::{f32::consts::PI, fs, io::ErrorKind};
+
+use bevy::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    score::JudgementWindows,
+    types::{Directions, LaneLayout, ScrollSpeed},
+};
+
+/// File the settings are kept in, next to where the game is run like `map.toml`
+const SETTINGS_FILE: &str = "settings.toml";
+
+/// Player settings, kept between sessions
+#[derive(Debug, Default)]
+pub struct Settings {
+    /// Milliseconds the song is heard after it's played, positive when audio is late.
+    /// Arrows are spawned and judged this much later.
+    pub offset: f64,
+    pub scroll: ScrollDirection,
+    pub scroll_speed: ScrollSpeed,
+    pub windows: JudgementWindows,
+}
+
+impl Settings {
+    /// Global offset in seconds
+    pub fn offset_seconds(&self) -> f64 {
+        self.offset / 1000.
+    }
+}
+
+/// Which way arrows scroll towards the targets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ScrollDirection {
+    /// Left to right, lanes stacked from the top
+    #[default]
+    Horizontal,
+    /// Bottom to top, lanes side by side from the left
+    Upscroll,
+    /// Top to bottom, lanes side by side from the left
+    Downscroll,
+}
+
+impl ScrollDirection {
+    /// The direction after this one, going back to the first after the last
+    pub fn next(self) -> Self {
+        match self {
+            Self::Horizontal => Self::Upscroll,
+            Self::Upscroll => Self::Downscroll,
+            Self::Downscroll => Self::Horizontal,
+        }
+    }
+
+    /// Returns where a point goes on screen. `along` goes from `SPAWN_POSITION` to `TARGET_POSITION`
+    /// like x does when scrolling horizontally, `across` is the lane's `LaneLayout::y`
+    pub fn place(&self, along: f32, across: f32) -> Vec2 {
+        match self {
+            Self::Horizontal => Vec2::new(along, across),
+            Self::Upscroll => Vec2::new(-across, along),
+            Self::Downscroll => Vec2::new(-across, -along),
+        }
+    }
+
+    /// Rotation of the way arrows move, 0 being left to right
+    pub fn rotation(&self) -> f32 {
+        match self {
+            Self::Horizontal => 0.,
+            Self::Upscroll => PI * 0.5,
+            Self::Downscroll => -PI * 0.5,
+        }
+    }
+
+    /// Way arrows fall off the screen once they're past the target
+    pub fn fall(&self) -> Vec2 {
+        match self {
+            Self::Horizontal | Self::Downscroll => Vec2::NEG_Y,
+            Self::Upscroll => Vec2::Y,
+        }
+    }
+}
+
+/// Keys that press each lane, for every number of lanes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "KeyBindingsToml")]
+pub struct KeyBindings {
+    pub four: Vec<Vec<KeyCode>>,
+    pub five: Vec<Vec<KeyCode>>,
+    pub six: Vec<Vec<KeyCode>>,
+    pub seven: Vec<Vec<KeyCode>>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        use KeyCode::*;
+
+        Self {
+            four: vec![vec![Up, D], vec![Down, F], vec![Left, J], vec![Right, K]],
+            five: vec![vec![D], vec![F], vec![Space], vec![J], vec![K]],
+            six: vec![vec![S], vec![D], vec![F], vec![J], vec![K], vec![L]],
+            seven: vec![vec![S], vec![D], vec![F], vec![Space], vec![J], vec![K], vec![L]],
+        }
+    }
+}
+
+impl KeyBindings {
+    fn layout(&self, layout: LaneLayout) -> &Vec<Vec<KeyCode>> {
+        match layout.lanes() {
+            4 => &self.four,
+            5 => &self.five,
+            6 => &self.six,
+            _ => &self.seven,
+        }
+    }
+
+    fn layout_mut(&mut self, layout: LaneLayout) -> &mut Vec<Vec<KeyCode>> {
+        match layout.lanes() {
+            4 => &mut self.four,
+            5 => &mut self.five,
+            6 => &mut self.six,
+            _ => &mut self.seven,
+        }
+    }
+
+    /// Keys for a lane, none if the settings file doesn't have any for it
+    pub fn keys(&self, layout: LaneLayout, lane: usize) -> &[KeyCode] {
+        self.layout(layout).get(lane).map_or(&[], Vec::as_slice)
+    }
+
+    /// Makes `key` the only key for `lane`, taking it away from any other lane
+    pub fn bind(&mut self, layout: LaneLayout, lane: usize, key: KeyCode) {
+        let lanes = self.layout_mut(layout);
+        for keys in lanes.iter_mut() {
+            keys.retain(|bound| *bound != key);
+        }
+
+        if lanes.len() <= lane {
+            lanes.resize(lane + 1, vec![]);
+        }
+        lanes[lane] = vec![key];
+    }
+
+    /// Checks if a key for this lane is being held
+    pub fn pressed(&self, layout: LaneLayout, lane: usize, input: &Input<KeyCode>) -> bool {
+        self.keys(layout, lane).iter().any(|code| input.pressed(*code))
+    }
+
+    /// Checks if a key for this lane has been pressed this frame
+    pub fn just_pressed(&self, layout: LaneLayout, lane: usize, input: &Input<KeyCode>) -> bool {
+        self.keys(layout, lane).iter().any(|code| input.just_pressed(*code))
+    }
+}
+
+/// Key bindings as found in the settings file. Older files have keys by direction,
+/// which are kept for 4 lane charts
+#[derive(Deserialize)]
+struct KeyBindingsToml {
+    four: Option<Vec<Vec<KeyCode>>>,
+    five: Option<Vec<Vec<KeyCode>>>,
+    six: Option<Vec<Vec<KeyCode>>>,
+    seven: Option<Vec<Vec<KeyCode>>>,
+    #[serde(default)]
+    up: Vec<KeyCode>,
+    #[serde(default)]
+    down: Vec<KeyCode>,
+    #[serde(default)]
+    left: Vec<KeyCode>,
+    #[serde(default)]
+    right: Vec<KeyCode>,
+}
+
+impl From<KeyBindingsToml> for KeyBindings {
+    fn from(file: KeyBindingsToml) -> Self {
+        let default = KeyBindings::default();
+
+        let by_direction = [file.up, file.down, file.left, file.right];
+        let old_four = by_direction
+            .iter()
+            .any(|keys| !keys.is_empty())
+            .then(|| by_direction.to_vec());
+
+        Self {
+            four: file.four.or(old_four).unwrap_or(default.four),
+            five: file.five.unwrap_or(default.five),
+            six: file.six.unwrap_or(default.six),
+            seven: file.seven.unwrap_or(default.seven),
+        }
+    }
+}
+
+/// Stick or trigger that presses a lane once pushed past `threshold`.
+/// A negative threshold is for pushing the axis the other way.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "AxisBindingToml")]
+pub struct AxisBinding {
+    pub axis: GamepadAxisType,
+    pub threshold: f32,
+    pub lane: usize,
+}
+
+impl AxisBinding {
+    pub fn pushed(&self, value: f32) -> bool {
+        if self.threshold < 0. {
+            value <= self.threshold
+        } else {
+            value >= self.threshold
+        }
+    }
+}
+
+/// Axis binding as found in the settings file, older files name a direction instead of a lane
+#[derive(Deserialize)]
+struct AxisBindingToml {
+    axis: GamepadAxisType,
+    threshold: f32,
+    lane: Option<usize>,
+    direction: Option<Directions>,
+}
+
+impl TryFrom<AxisBindingToml> for AxisBinding {
+    type Error = &'static str;
+
+    fn try_from(file: AxisBindingToml) -> Result<Self, Self::Error> {
+        let lane = match (file.lane, file.direction) {
+            (Some(lane), _) => lane,
+            (None, Some(direction)) => direction.lane(),
+            (None, None) => return Err("axis binding needs a lane"),
+        };
+        Ok(Self { axis: file.axis, threshold: file.threshold, lane })
+    }
+}
+
+/// Gamepad buttons and axes that press each lane of one layout, lanes past the end of `lanes` have no buttons
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GamepadLanes {
+    #[serde(default)]
+    pub lanes: Vec<Vec<GamepadButtonType>>,
+    #[serde(default)]
+    pub axes: Vec<AxisBinding>,
+}
+
+impl GamepadLanes {
+    pub fn buttons(&self, lane: usize) -> &[GamepadButtonType] {
+        self.lanes.get(lane).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Gamepad buttons and axes that press each lane, for every number of lanes like `KeyBindings`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "GamepadMappingToml")]
+pub struct GamepadMapping {
+    pub four: GamepadLanes,
+    pub five: GamepadLanes,
+    pub six: GamepadLanes,
+    pub seven: GamepadLanes,
+}
+
+impl Default for GamepadMapping {
+    fn default() -> Self {
+        use GamepadButtonType::*;
+
+        let lanes = |lanes: &[&[GamepadButtonType]]| lanes.iter().map(|buttons| buttons.to_vec()).collect();
+        let axis = |axis, threshold, lane| AxisBinding { axis, threshold, lane };
+        Self {
+            // Up, Down, Left then Right, on the D-pad, the face buttons or the left stick
+            four: GamepadLanes {
+                lanes: lanes(&[&[DPadUp, North], &[DPadDown, South], &[DPadLeft, West], &[DPadRight, East]]),
+                axes: vec![
+                    axis(GamepadAxisType::LeftStickY, 0.5, 0),
+                    axis(GamepadAxisType::LeftStickY, -0.5, 1),
+                    axis(GamepadAxisType::LeftStickX, -0.5, 2),
+                    axis(GamepadAxisType::LeftStickX, 0.5, 3),
+                ],
+            },
+            // Left hand lanes on the D-pad, right hand ones on the face buttons,
+            // outer lanes on the shoulders and a middle lane on both triggers
+            five: GamepadLanes {
+                lanes: lanes(&[&[DPadLeft], &[DPadDown], &[LeftTrigger2, RightTrigger2], &[South], &[East]]),
+                axes: vec![],
+            },
+            six: GamepadLanes {
+                lanes: lanes(&[&[LeftTrigger], &[DPadLeft], &[DPadDown], &[South], &[East], &[RightTrigger]]),
+                axes: vec![],
+            },
+            seven: GamepadLanes {
+                lanes: lanes(&[
+                    &[LeftTrigger],
+                    &[DPadLeft],
+                    &[DPadDown],
+                    &[LeftTrigger2, RightTrigger2],
+                    &[South],
+                    &[East],
+                    &[RightTrigger],
+                ]),
+                axes: vec![],
+            },
+        }
+    }
+}
+
+impl GamepadMapping {
+    /// Buttons and axes for a number of lanes
+    pub fn layout(&self, layout: LaneLayout) -> &GamepadLanes {
+        match layout.lanes() {
+            4 => &self.four,
+            5 => &self.five,
+            6 => &self.six,
+            _ => &self.seven,
+        }
+    }
+}
+
+/// Gamepad mapping as found in the settings file. Older files have a single 4 lane mapping,
+/// with buttons in a `lanes` list or by direction, which is kept for 4 lane charts
+#[derive(Deserialize)]
+struct GamepadMappingToml {
+    four: Option<GamepadLanes>,
+    five: Option<GamepadLanes>,
+    six: Option<GamepadLanes>,
+    seven: Option<GamepadLanes>,
+    #[serde(default)]
+    lanes: Vec<Vec<GamepadButtonType>>,
+    #[serde(default)]
+    up: Vec<GamepadButtonType>,
+    #[serde(default)]
+    down: Vec<GamepadButtonType>,
+    #[serde(default)]
+    left: Vec<GamepadButtonType>,
+    #[serde(default)]
+    right: Vec<GamepadButtonType>,
+    #[serde(default)]
+    axes: Vec<AxisBinding>,
+}
+
+impl From<GamepadMappingToml> for GamepadMapping {
+    fn from(file: GamepadMappingToml) -> Self {
+        let default = GamepadMapping::default();
+
+        let by_direction = [file.up, file.down, file.left, file.right];
+        let lanes = if file.lanes.is_empty() && by_direction.iter().any(|buttons| !buttons.is_empty()) {
+            by_direction.to_vec()
+        } else {
+            file.lanes
+        };
+        let old_four = if lanes.is_empty() && file.axes.is_empty() {
+            None
+        } else {
+            Some(GamepadLanes { lanes, axes: file.axes })
+        };
+
+        Self {
+            four: file.four.or(old_four).unwrap_or(default.four),
+            five: file.five.unwrap_or(default.five),
+            six: file.six.unwrap_or(default.six),
+            seven: file.seven.unwrap_or(default.seven),
+        }
+    }
+}
+
+/// Mapping used for a gamepad that doesn't match the usual layout, like a dance mat
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GamepadOverride {
+    /// Id of the gamepad, in the order they were connected starting from 0
+    pub id: usize,
+    pub mapping: GamepadMapping,
+}
+
+/// How gamepads press each lane
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GamepadBindings {
+    #[serde(default)]
+    pub default: GamepadMapping,
+    /// Left out when empty, toml can't write a plain value after the `default` table
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overrides: Vec<GamepadOverride>,
+}
+
+impl GamepadBindings {
+    pub fn mapping(&self, gamepad: Gamepad) -> &GamepadMapping {
+        self.overrides
+            .iter()
+            .find(|gamepad_override| gamepad_override.id == gamepad.id)
+            .map_or(&self.default, |gamepad_override| &gamepad_override.mapping)
+    }
+}
+
+/// Contents of the settings file
+#[derive(Default, Serialize, Deserialize)]
+struct SettingsToml {
+    #[serde(default)]
+    offset: f64,
+    #[serde(default)]
+    scroll: ScrollDirection,
+    #[serde(default)]
+    scroll_speed: ScrollSpeed,
+    /// Milliseconds either way from the target for each judgement
+    #[serde(default)]
+    windows: JudgementWindows,
+    #[serde(default)]
+    keys: KeyBindings,
+    #[serde(default)]
+    gamepad: GamepadBindings,
+}
+
+/// Reads the settings file, falling back to defaults if there's none or it can't be read
+fn load_settings() -> SettingsToml {
+    let text = match fs::read_to_string(SETTINGS_FILE) {
+        Ok(text) => text,
+        Err(error) if error.kind() == ErrorKind::NotFound => return SettingsToml::default(),
+        Err(error) => {
+            warn!("Couldn't read {}: {}", SETTINGS_FILE, error);
+            return SettingsToml::default();
+        }
+    };
+
+    toml::from_str(&text).unwrap_or_else(|error| {
+        warn!("Couldn't parse {}: {}", SETTINGS_FILE, error);
+        SettingsToml::default()
+    })
+}
+
+pub fn save_settings(settings: &Settings, keys: &KeyBindings, gamepad: &GamepadBindings) {
+    let file = SettingsToml {
+        offset: settings.offset,
+        scroll: settings.scroll,
+        scroll_speed: settings.scroll_speed,
+        windows: settings.windows,
+        keys: keys.clone(),
+        gamepad: gamepad.clone(),
+    };
+    let text = toml::to_string(&file).expect("Couldn't convert settings to toml text");
+    if let Err(error) = fs::write(SETTINGS_FILE, text) {
+        warn!("Couldn't write {}: {}", SETTINGS_FILE, error);
+    }
+}
+
+pub struct SettingsPlugin;
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        let file = load_settings();
+
+        app.insert_resource(Settings {
+               offset: file.offset,
+               scroll: file.scroll,
+               // Hand edited speeds could stop arrows or make them all spawn at once
+               scroll_speed: file.scroll_speed.clamped(),
+               windows: file.windows.checked(),
+           })
+           .insert_resource(file.keys)
+           .insert_resource(file.gamepad);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_round_trip() {
+        let text = toml::to_string(&SettingsToml::default()).unwrap();
+        let file: SettingsToml = toml::from_str(&text).unwrap();
+
+        assert_eq!(file.keys.four, KeyBindings::default().four);
+        assert!(file.gamepad.overrides.is_empty());
+    }
+
+    #[test]
+    fn settings_round_trip_with_gamepad_override() {
+        let mut settings = SettingsToml::default();
+        settings.gamepad.overrides.push(GamepadOverride { id: 1, mapping: GamepadMapping::default() });
+
+        let text = toml::to_string(&settings).unwrap();
+        let file: SettingsToml = toml::from_str(&text).unwrap();
+
+        assert_eq!(file.gamepad.overrides.len(), 1);
+        assert_eq!(file.gamepad.overrides[0].id, 1);
+    }
+
+    #[test]
+    fn gamepad_mapping_reads_direction_format() {
+        let mapping: GamepadMapping = toml::from_str(
+            r#"
+            up = ["DPadUp"]
+            down = ["DPadDown"]
+            left = ["DPadLeft"]
+            right = ["DPadRight"]
+            axes = [{ axis = "LeftStickX", threshold = 0.5, direction = "Right" }]
+            "#,
+        )
+        .unwrap();
+
+        let four = mapping.layout(LaneLayout::new(4).unwrap());
+        assert_eq!(four.buttons(2), [GamepadButtonType::DPadLeft]);
+        assert_eq!(four.axes[0].lane, 3);
+        assert_eq!(mapping.layout(LaneLayout::new(7).unwrap()).lanes.len(), 7);
+    }
+
+    #[test]
+    fn gamepad_mapping_reads_single_lanes_format() {
+        let mapping: GamepadMapping = toml::from_str(r#"lanes = [["South"], ["East"]]"#).unwrap();
+
+        let four = mapping.layout(LaneLayout::new(4).unwrap());
+        assert_eq!(four.buttons(1), [GamepadButtonType::East]);
+        assert!(four.buttons(2).is_empty());
+        assert_eq!(mapping.layout(LaneLayout::new(5).unwrap()).lanes.len(), 5);
+    }
+
+    #[test]
+    fn gamepad_mapping_round_trips() {
+        let text = toml::to_string(&SettingsToml::default()).unwrap();
+        let bindings = toml::from_str::<SettingsToml>(&text).unwrap().gamepad;
+
+        for lanes in LaneLayout::MIN_LANES..=LaneLayout::MAX_LANES {
+            let layout = LaneLayout::new(lanes).unwrap();
+            assert_eq!(bindings.default.layout(layout).lanes, GamepadMapping::default().layout(layout).lanes);
+        }
+    }
+
+    #[test]
+    fn binding_a_key_takes_it_from_other_lanes() {
+        let layout = LaneLayout::new(4).unwrap();
+        let mut keys = KeyBindings::default();
+
+        keys.bind(layout, 0, KeyCode::F);
+
+        assert_eq!(keys.keys(layout, 0), [KeyCode::F]);
+        assert_eq!(keys.keys(layout, 1), [KeyCode::Down]);
+        // Other layouts keep their own keys
+        assert_eq!(keys.keys(LaneLayout::new(5).unwrap(), 1), [KeyCode::F]);
+    }
+
+    #[test]
+    fn binding_a_missing_lane_adds_it() {
+        let layout = LaneLayout::new(6).unwrap();
+        let mut keys = KeyBindings { six: vec![vec![KeyCode::A]], ..Default::default() };
+
+        keys.bind(layout, 3, KeyCode::A);
+
+        assert!(keys.keys(layout, 0).is_empty());
+        assert!(keys.keys(layout, 2).is_empty());
+        assert_eq!(keys.keys(layout, 3), [KeyCode::A]);
+    }
+
+    #[test]
+    fn judgement_windows_are_read_and_checked() {
+        let file: SettingsToml = toml::from_str("[windows]\nperfect = 30.0\ngreat = 60.0").unwrap();
+        assert_eq!(file.windows.checked().perfect, 30.);
+        assert_eq!(file.windows.checked().bad, JudgementWindows::default().bad);
+
+        let file: SettingsToml = toml::from_str("[windows]\nperfect = 100.0\ngreat = 60.0").unwrap();
+        assert_eq!(file.windows.checked(), JudgementWindows::default());
+
+        let file: SettingsToml = toml::from_str("[windows]\ngood = -1.0").unwrap();
+        assert_eq!(file.windows.checked(), JudgementWindows::default());
+    }
+
+    #[test]
+    fn key_bindings_read_direction_format() {
+        let file: SettingsToml = toml::from_str(
+            r#"
+            offset = 12.0
+
+            [keys]
+            up = ["W"]
+            down = ["S"]
+            left = ["A"]
+            right = ["D"]
+            "#,
+        )
+        .unwrap();
+
+        let four = LaneLayout::new(4).unwrap();
+        assert_eq!(file.offset, 12.);
+        assert_eq!(file.keys.keys(four, 0), [KeyCode::W]);
+        assert_eq!(file.keys.keys(four, 3), [KeyCode::D]);
+        assert_eq!(file.keys.five, KeyBindings::default().five);
+    }
+}
@@ -10,106 +10,91 @@ use bevy::{
         render_resource::*,
         Extract, RenderApp, RenderStage,
     },
-    sprite::{Material2d, Material2dPlugin, MaterialMesh2dBundle, RenderMaterials2d, SpriteBundle },
+    sprite::{Material2d, Material2dPlugin, MaterialMesh2dBundle, RenderMaterials2d},
 };
 
-pub const CLEAR: Color = Color::rgb(0.3, 0.3, 0.3);
-pub const HEIGHT: f32 = 900.0;
-pub const RESOLUTION: f32 = 16.0 / 9.0;
+use crate::consts::{AppState, START_HEALTH};
+
+/// Size of the health bar, it's vertical and sits on the right edge of the window
+const HEALTH_BAR_SIZE: Vec2 = Vec2::new(24., 400.);
 
 #[derive(AsBindGroup, TypeUuid, Clone)]
 #[uuid = "f690fdae-d598-45ab-8225-97e2a3f056e0"]
-pub struct CoolMaterial {
+pub struct HealthMaterial {
     #[uniform(0)]
     color: Color,
     #[uniform(0)]
+    health: f32,
+    #[uniform(0)]
     time: f32,
-    #[texture(1)]
-    #[sampler(2)]
-    image: Handle<Image>,
 }
 
-impl Material2d for CoolMaterial {
+impl Material2d for HealthMaterial {
     fn fragment_shader() -> ShaderRef {
-        "shaders/background.wgsl".into()
+        "shaders/health.wgsl".into()
     }
 }
 
 #[derive(Clone, ShaderType)]
-struct CoolMaterialUniformData {
+struct HealthMaterialUniformData {
     color: Color,
+    health: f32,
     time: f32,
 }
 
+/// Player's life, from 0 to 1. Lives on the health bar, which renders it
 #[derive(Component, Clone, Copy)]
-struct Health {
-    value: f32,
+pub struct Health {
+    pub value: f32,
 }
 
-pub struct CustomMaterialPlugin;
+pub struct HealthMaterialPlugin;
 
-impl Plugin for CustomMaterialPlugin {
+impl Plugin for HealthMaterialPlugin {
     fn build(&self,app: &mut App) {
-        app.add_plugin(Material2dPlugin::<CoolMaterial>::default())
+        app.add_plugin(Material2dPlugin::<HealthMaterial>::default())
         .add_plugin(ExtractResourcePlugin::<ExtractedTime>::default())
-        .add_startup_system(setup);
+        .add_system_set(
+            SystemSet::on_enter(AppState::Game)
+                .with_system(setup_health_bar)
+        )
+        .add_system_set(
+            SystemSet::on_exit(AppState::Game)
+                .with_system(tear_down_health_bar)
+        );
         // Add all render world systems/resources
         app.sub_app_mut(RenderApp)
         .add_system_to_stage(RenderStage::Extract, extract_health)
-        .add_system_to_stage(RenderStage::Prepare, prepare_my_material);
-    }
-}
-
-#[derive(Component)]
-struct Rect;
-
-fn make_rect(x: f32, y: f32, z: f32) -> SpriteBundle {
-    let sprite = Sprite {
-        color: Color::rgb(255.0, 255.0, 255.0),
-        flip_x: false,
-        flip_y: false,
-        custom_size: Some(Vec2::new(35.0, 35.0)),
-        anchor: Default::default(),
-    };
-
-    SpriteBundle {
-        sprite: sprite,
-        transform: Transform::from_xyz(0.0 + x, 0.0 + y, 0.0 + z),
-        ..Default::default()
+        .add_system_to_stage(RenderStage::Prepare, prepare_health_material);
     }
 }
 
-fn setup(
+fn setup_health_bar(
     mut commands: Commands,
     mut mesh_assets: ResMut<Assets<Mesh>>,
-    mut my_material_assets: ResMut<Assets<CoolMaterial>>,
-    assets: Res<AssetServer>,
+    mut my_material_assets: ResMut<Assets<HealthMaterial>>,
+    window: Res<WindowDescriptor>,
 ) {
+    let x = window.width / 2. - HEALTH_BAR_SIZE.x;
+
     commands
         .spawn_bundle(MaterialMesh2dBundle {
-            mesh: mesh_assets.add(Mesh::from(shape::Quad::new(Vec2 { x: 200., y: 200. }))).into(),
-            material: my_material_assets.add(CoolMaterial {
-                color: Color::rgb(0.0, 1.0, 0.3),
+            mesh: mesh_assets.add(Mesh::from(shape::Quad::new(HEALTH_BAR_SIZE))).into(),
+            material: my_material_assets.add(HealthMaterial {
+                color: Color::GREEN,
+                health: START_HEALTH,
                 time: 0.0,
-                image: assets.load("images/awesome.png"),
             }),
-            transform: Transform::from_xyz(50., 0., 15.),    
+            transform: Transform::from_xyz(x, 0., 15.),
             ..default()
         })
-        .insert(Health { value: 0.2 });
-
-    // commands
-    //     .spawn_bundle(MaterialMesh2dBundle {
-    //         mesh: mesh_assets.add(Mesh::from(shape::Quad::new(Vec2 { x: 200., y: 200. }))).into(),
-    //         material: my_material_assets.add(CoolMaterial {
-    //             color: Color::rgb(0.0, 1.0, 0.3),
-    //             time: 0.0,
-    //             image: assets.load("images/awesome.png"),
-    //         }),
-    //         transform: Transform::from_xyz(-200., 0., 15.),    
-    //         ..default()
-    //     })
-    //     .insert(Health { value: 0.8 });
+        .insert(Health { value: START_HEALTH });
+}
+
+fn tear_down_health_bar(mut commands: Commands, query: Query<Entity, With<Health>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
 }
 
 struct ExtractedTime {
@@ -128,7 +113,7 @@ impl ExtractResource for ExtractedTime {
 
 fn extract_health(
     mut commands: Commands,
-    health_query: Extract<Query<(Entity, &Health, &Handle<CoolMaterial>)>>,
+    health_query: Extract<Query<(Entity, &Health, &Handle<HealthMaterial>)>>,
 ) {
     for (entity, health, handle) in health_query.iter() {
         commands
@@ -138,25 +123,27 @@ fn extract_health(
     }
 }
 
-fn prepare_my_material(
-    materials: Res<RenderMaterials2d<CoolMaterial>>,
-    health_query: Query<(&Health, &Handle<CoolMaterial>)>,
+fn prepare_health_material(
+    materials: Res<RenderMaterials2d<HealthMaterial>>,
+    health_query: Query<(&Health, &Handle<HealthMaterial>)>,
     time: Res<ExtractedTime>,
     render_queue: Res<RenderQueue>,
 ) {
     for (health, handle) in health_query.iter() {
         if let Some(material) = materials.get(handle) {
-            let binding = &material.bindings[2];
+            let binding = &material.bindings[0];
             if let OwnedBindingResource::Buffer(cur_buffer) = binding {
                 let mut buffer = encase::UniformBuffer::new(Vec::new());
                 buffer
-                    .write(&CoolMaterialUniformData {
-                        color: Color::rgb(health.value, 0.0, 0.0),
-                        time: time.seconds_since_startup % 1.0,
+                    .write(&HealthMaterialUniformData {
+                        // Goes from red to green as health fills up
+                        color: Color::rgb(1.0 - health.value, health.value, 0.2),
+                        health: health.value,
+                        time: time.seconds_since_startup,
                     })
                     .unwrap();
                 render_queue.write_buffer(cur_buffer, 0, buffer.as_ref());
             }
         }
     }
-}
\ No newline at end of file
+}
@@ -11,7 +11,8 @@ use bevy::{
         Res, ResMut, Assets,
         shape::Quad,
         Transform,
-        Vec3, EventReader,
+        Vec3, Quat, EventReader,
+        With, SystemSet, DespawnRecursiveExt,
     },
     render::{
         render_resource::{
@@ -27,7 +28,7 @@ use bevy::{
     time::Time
 };
 
-use crate::{types::Directions, consts::TARGET_POSITION, arrows::CorrectArrowEvent};
+use crate::{types::LaneLayout, settings::Settings, consts::{AppState, TARGET_POSITION}, arrows::CorrectArrowEvent};
 
 // Resources to Extract for use in shader
 pub struct ExtractedTime {
@@ -44,7 +45,7 @@ impl ExtractResource for ExtractedTime {
 
 #[derive(Component)]
 pub struct TargetArrowSparkle {
-    direction: Directions
+    lane: usize
 }
 
 #[derive(Component, Debug, Clone, Copy)]
@@ -131,20 +132,17 @@ fn setup_target_arrows_sparkle(
     mut commands: Commands,
     mut my_material_assets: ResMut<Assets<ArrowSparkleMaterial>>,
     mut mesh_assets: ResMut<Assets<Mesh>>,
+    layout: Res<LaneLayout>,
+    settings: Res<Settings>,
 ) {
-    use Directions::*;
-    let directions = [Up, Down, Left, Right];
-
-    for direction in directions.iter() {
-        let z = match direction {
-            Up => 0.3,
-            Down => 0.4,
-            Left => 0.5,
-            Right => 0.6,
-        };
-
-        let mut transform = Transform::from_translation(Vec3::new(TARGET_POSITION, direction.y(), z));
-        transform.scale = Vec3::new(300., 300., 1.);
+    for lane in layout.iter() {
+        // Each lane gets its own depth so overlapping sparkles don't fight
+        let z = 0.3 + 0.1 * lane as f32;
+
+        let position = settings.scroll.place(TARGET_POSITION, layout.y(lane));
+        let mut transform = Transform::from_translation(position.extend(z));
+        transform.rotate(Quat::from_rotation_z(layout.rotation(lane, settings.scroll.rotation())));
+        transform.scale = Vec3::new(300. * layout.scale(), 300. * layout.scale(), 1.);
         commands.spawn_bundle(MaterialMesh2dBundle {
             material: my_material_assets.add(ArrowSparkleMaterial { time: 0., last_time: 1., points: 0.5 }),
             mesh: mesh_assets.add(Mesh::from(Quad::default())).into(),
@@ -156,11 +154,20 @@ fn setup_target_arrows_sparkle(
             points: 0.
         })
         .insert(TargetArrowSparkle {
-            direction: *direction
+            lane
         });
     }
 }
 
+pub fn tear_down_target_arrows_sparkle(
+    mut commands: Commands,
+    query: Query<Entity, With<TargetArrowSparkle>>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
 pub fn correct_arrow_event_listener(
     time: Res<Time>,
     mut correct_event_reader: EventReader<CorrectArrowEvent>,
@@ -168,9 +175,10 @@ pub fn correct_arrow_event_listener(
 ) {
     for event in correct_event_reader.iter() {
         for (arrow, mut last_correct) in query.iter_mut() {
-            if arrow.direction == event.direction {
+            if arrow.lane == event.lane {
                 last_correct.last_time = time.seconds_since_startup() as f32;
-                last_correct.points = event.points as f32 / 100.;
+                // Combo bonus is left out, the sparkle only shows accuracy
+                last_correct.points = event.judgement.points() as f32 / 100.;
             }
         }
     }
@@ -181,7 +189,14 @@ impl Plugin for ArrowSparkleMaterialPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(Material2dPlugin::<ArrowSparkleMaterial>::default())
            .add_plugin(ExtractResourcePlugin::<ExtractedTime>::default())
-           .add_startup_system(setup_target_arrows_sparkle)
+           .add_system_set(
+                SystemSet::on_enter(AppState::Game)
+                    .with_system(setup_target_arrows_sparkle)
+           )
+           .add_system_set(
+                SystemSet::on_exit(AppState::Game)
+                    .with_system(tear_down_target_arrows_sparkle)
+           )
            .add_system(correct_arrow_event_listener);
         app.sub_app_mut(RenderApp)
            .add_system_to_stage(RenderStage::Extract, extract_time_since_correct)
@@ -3,20 +3,35 @@ use bevy::{
     utils::{Duration, Instant},
 };
 
-use crate::consts::AppState;
+use crate::{
+    audio::SongPosition,
+    chart::{Chart, SongConfig},
+    consts::*,
+    practice::{SeekEvent, SendSeek},
+    settings::Settings,
+};
+
+/// Drift from the song, in seconds, past which the clock jumps straight to the song instead of easing towards it
+const MAX_SONG_DRIFT: f64 = 0.1;
+
+/// Fraction of the drift from the song corrected each frame
+const SONG_SYNC_RATE: f64 = 0.1;
 
 pub struct ControlledTime {
     delta: Duration,
     last_update: Option<Instant>,
-    delta_seconds_f64: f64,
-    delta_seconds: f32,
     seconds_since_startup: f64,
-    startup: Instant
+    startup: Instant,
+    paused_at: Option<Instant>,
+    /// Seconds after startup at which the song starts
+    lead_in: f64,
+    /// How fast the song is played, 1 being normal speed
+    rate: f64,
 }
 
 impl Default for ControlledTime {
     fn default() -> Self {
-        Self { delta: Duration::from_secs(0), last_update: None, delta_seconds_f64: 0.0, delta_seconds: 0.0, seconds_since_startup: 0.0, startup: Instant::now() }
+        Self { delta: Duration::from_secs(0), last_update: None, seconds_since_startup: 0.0, startup: Instant::now(), paused_at: None, lead_in: DEFAULT_LEAD_IN, rate: 1. }
     }
 }
 
@@ -26,6 +41,20 @@ impl ControlledTime {
         self.seconds_since_startup = 0.0;
     }
 
+    /// Stops the clock until `resume` is called
+    pub fn pause(&mut self) {
+        self.paused_at = Some(Instant::now());
+    }
+
+    /// Starts the clock again from where it was paused
+    pub fn resume(&mut self) {
+        if let Some(paused_at) = self.paused_at.take() {
+            let paused_for = Instant::now() - paused_at;
+            self.startup += paused_for;
+            self.last_update = self.last_update.map(|last_update| last_update + paused_for);
+        }
+    }
+
     pub fn update(&mut self) {
         let now = Instant::now();
         self.update_with_instant(now);
@@ -34,8 +63,6 @@ impl ControlledTime {
     pub fn update_with_instant(&mut self, instant: Instant) {
         if let Some(last_update) = self.last_update {
             self.delta = instant - last_update;
-            self.delta_seconds_f64 = self.delta.as_secs_f64();
-            self.delta_seconds = self.delta.as_secs_f32();
         }
 
         let duration_since_startup = instant - self.startup;
@@ -43,30 +70,124 @@ impl ControlledTime {
         self.last_update = Some(instant);
     }
 
-    #[inline]
-    pub fn delta_seconds(&self) -> f32 {
-        self.delta_seconds
+    pub fn set_lead_in(&mut self, lead_in: f64) {
+        self.lead_in = lead_in;
+    }
+
+    /// Sets how fast the next song is played, between `MIN_RATE` and `MAX_RATE`.
+    /// The clock keeps running in real seconds, arrow times are scaled by the rate instead
+    pub fn set_rate(&mut self, rate: f64) {
+        // Rounded so stepping through rates doesn't pile up float errors
+        let rate = (rate / RATE_STEP).round() * RATE_STEP;
+        self.rate = rate.clamp(MIN_RATE, MAX_RATE);
     }
 
     #[inline]
-    pub fn delta_seconds_f64(&self) -> f64 {
-        self.delta_seconds_f64
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Jumps to `seconds` into the song, as `song_seconds` counts them.
+    /// Can't go further back than the start of the lead-in
+    pub fn seek(&mut self, seconds: f64) {
+        let now = Instant::now();
+        let since_startup = (seconds + self.lead_in).max(0.);
+
+        self.startup = now - Duration::from_secs_f64(since_startup);
+        self.seconds_since_startup = since_startup;
+        self.last_update = Some(now);
+    }
+
+    /// Moves the clock towards `seconds`, the time into the song that's being heard.
+    /// The song position only moves when the audio device asks for more samples,
+    /// so small differences are eased out instead of making arrows jitter.
+    pub fn sync_to_song(&mut self, seconds: f64) {
+        let drift = seconds + self.lead_in - self.seconds_since_startup;
+        let correction = if drift.abs() > MAX_SONG_DRIFT { drift } else { drift * SONG_SYNC_RATE };
+
+        // Moving startup back makes the clock go forward
+        if correction >= 0. {
+            self.startup -= Duration::from_secs_f64(correction);
+        } else {
+            self.startup += Duration::from_secs_f64(-correction);
+        }
+        self.seconds_since_startup += correction;
     }
 
     #[inline]
     pub fn seconds_since_startup(&self) -> f64 {
         self.seconds_since_startup
     }
+
+    /// Seconds into the song, what arrows are timed against. Negative during the lead-in.
+    /// These are real seconds, a song played at half rate lasts twice as long
+    #[inline]
+    pub fn song_seconds(&self) -> f64 {
+        self.seconds_since_startup - self.lead_in
+    }
+
+    /// Seconds into the song as written in the chart, following the playback rate
+    #[inline]
+    pub fn chart_seconds(&self) -> f64 {
+        self.song_seconds() * self.rate
+    }
 }
 
-pub fn update_time(mut time: ResMut<ControlledTime>) {
+pub fn update_time(
+    mut time: ResMut<ControlledTime>,
+    song_position: Option<Res<SongPosition>>,
+    settings: Res<Settings>,
+    seek_events: EventReader<SeekEvent>,
+) {
     time.update();
+
+    // This runs before seeks are sent, so it sees them the frame after, when the position
+    // can still be the one of the song being left. Syncing to it would undo the seek
+    let seeking = !seek_events.is_empty();
+    seek_events.clear();
+    if seeking {
+        return;
+    }
+
+    // Once the song plays the clock follows what's heard of it
+    if let Some(seconds) = song_position.and_then(|position| position.seconds()) {
+        // The song position is in chart seconds, the offset in real ones
+        let seconds = seconds / time.rate();
+        time.sync_to_song(seconds - settings.offset_seconds());
+    }
 }
 
 pub fn reset_time_when_entering_game(mut time: ResMut<ControlledTime>) {
     time.reset_time();
 }
 
+/// Uses the lead-in of the chart being played
+pub fn set_lead_in_when_entering_game(
+    mut time: ResMut<ControlledTime>,
+    song_config: Res<SongConfig>,
+    charts: Res<Assets<Chart>>,
+) {
+    if let Some(chart) = charts.get(&song_config.chart) {
+        time.set_lead_in(chart.lead_in);
+    }
+}
+
+/// Jumps a bit before the point seeked to, so its arrows have time to come in
+pub fn seek_time(mut time: ResMut<ControlledTime>, mut seek_events: EventReader<SeekEvent>) {
+    if let Some(seek) = seek_events.iter().last() {
+        let seconds = seek.seconds / time.rate() - PRACTICE_LEAD_IN;
+        time.seek(seconds);
+    }
+}
+
+pub fn pause_time(mut time: ResMut<ControlledTime>) {
+    time.pause();
+}
+
+pub fn resume_time(mut time: ResMut<ControlledTime>) {
+    time.resume();
+}
+
 pub struct TimePlugin;
 impl Plugin for TimePlugin {
     fn build(&self, app: &mut App) {
@@ -74,6 +195,8 @@ impl Plugin for TimePlugin {
            .add_system_set(
                 SystemSet::on_update(AppState::Game)
                     .with_system(update_time)
+                    // After syncing, so the song being left doesn't pull the clock back
+                    .with_system(seek_time.after(update_time).after(SendSeek))
            )
            .add_system_set(
                 SystemSet::on_update(AppState::MakeMap)
@@ -82,6 +205,15 @@ impl Plugin for TimePlugin {
            .add_system_set(
                 SystemSet::on_enter(AppState::Game)
                     .with_system(reset_time_when_entering_game)
+                    .with_system(set_lead_in_when_entering_game)
+           )
+           .add_system_set(
+                SystemSet::on_pause(AppState::Game)
+                    .with_system(pause_time)
+           )
+           .add_system_set(
+                SystemSet::on_resume(AppState::Game)
+                    .with_system(resume_time)
            )
            .add_system_set(
                 SystemSet::on_enter(AppState::MakeMap)
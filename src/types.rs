@@ -1,10 +1,10 @@
 use crate::consts::*;
-use bevy::prelude::{Handle, AudioSource, AssetServer};
-use bevy::input::{keyboard::KeyCode, Input};
 use core::f32::consts::PI;
 use serde_derive::{Deserialize, Serialize};
-use std::fs::File;
-use std::io::prelude::*;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
 
 #[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub enum Directions {
@@ -15,28 +15,8 @@ pub enum Directions {
 }
 
 impl Directions {
-    pub fn key_pressed(&self, input: &Input<KeyCode>) -> bool {
-        let keys = match self {
-            Directions::Up => [KeyCode::Up, KeyCode::D],
-            Directions::Down => [KeyCode::Down, KeyCode::F],
-            Directions::Left => [KeyCode::Left, KeyCode::J],
-            Directions::Right => [KeyCode::Right, KeyCode::K],
-        };
-
-        keys.iter().any(|code| input.pressed(*code))
-    }
-
-    /// Checks if a key that corresponds to this direciton has been pressed
-    pub fn key_jest_pressed(&self, input: &Input<KeyCode>) -> bool {
-        let keys = match self {
-            Directions::Up => [KeyCode::Up, KeyCode::D],
-            Directions::Down => [KeyCode::Down, KeyCode::F],
-            Directions::Left => [KeyCode::Left, KeyCode::J],
-            Directions::Right => [KeyCode::Right, KeyCode::K],
-        };
-
-        keys.iter().any(|code| input.just_pressed(*code))
-    }
+    /// Directions in lane order, for 4 lane charts
+    pub const ALL: [Directions; 4] = [Directions::Up, Directions::Down, Directions::Left, Directions::Right];
 
     /// Returns the correct rotation for an arrow with this direction
     pub fn rotation(&self) -> f32 {
@@ -48,13 +28,90 @@ impl Directions {
         }
     }
 
-    /// Returns the correct y coordinate for an arrow with this direction
-    pub fn y(&self) -> f32 {
+    /// Lane of this direction in a 4 lane chart
+    pub fn lane(&self) -> usize {
         match self {
-            Directions::Up => 150.,
-            Directions::Down => 50.,
-            Directions::Left => -50.,
-            Directions::Right => -150.,
+            Directions::Up => 0,
+            Directions::Down => 1,
+            Directions::Left => 2,
+            Directions::Right => 3,
+        }
+    }
+}
+
+/// How many lanes a chart has, and where they go on screen. Lanes are numbered from the top
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LaneLayout {
+    lanes: usize,
+}
+
+impl Default for LaneLayout {
+    fn default() -> Self {
+        Self { lanes: 4 }
+    }
+}
+
+impl LaneLayout {
+    pub const MIN_LANES: usize = 4;
+    pub const MAX_LANES: usize = 7;
+
+    /// `None` if there are too few or too many lanes
+    pub fn new(lanes: usize) -> Option<Self> {
+        (Self::MIN_LANES..=Self::MAX_LANES).contains(&lanes).then_some(Self { lanes })
+    }
+
+    pub fn lanes(&self) -> usize {
+        self.lanes
+    }
+
+    /// Every lane, from the top
+    pub fn iter(&self) -> std::ops::Range<usize> {
+        0..self.lanes
+    }
+
+    /// Distance between lanes, they get closer when there are too many to fit
+    fn spacing(&self) -> f32 {
+        (LANES_HEIGHT / self.lanes as f32).min(100.)
+    }
+
+    /// How big arrows are drawn compared to a 4 lane chart
+    pub fn scale(&self) -> f32 {
+        self.spacing() / 100.
+    }
+
+    /// Returns the y coordinate of a lane
+    pub fn y(&self, lane: usize) -> f32 {
+        ((self.lanes - 1) as f32 / 2. - lane as f32) * self.spacing()
+    }
+
+    /// Returns the rotation of arrows in a lane. Only 4 lane charts have directions,
+    /// arrows in other layouts all point the way they move, given by `moving`
+    pub fn rotation(&self, lane: usize, moving: f32) -> f32 {
+        match Directions::ALL.get(lane) {
+            Some(direction) if self.lanes == 4 => direction.rotation(),
+            _ => moving,
+        }
+    }
+
+    /// Name of a lane as shown to the player
+    pub fn lane_name(&self, lane: usize) -> String {
+        match Directions::ALL.get(lane) {
+            Some(direction) if self.lanes == 4 => format!("{:?}", direction),
+            _ => format!("Lane {}", lane + 1),
+        }
+    }
+}
+
+impl FromStr for Directions {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Up" => Ok(Directions::Up),
+            "Down" => Ok(Directions::Down),
+            "Left" => Ok(Directions::Left),
+            "Right" => Ok(Directions::Right),
+            _ => Err(()),
         }
     }
 }
@@ -82,67 +139,733 @@ impl Speed {
     }
 }
 
+/// How fast arrows scroll, picked by the player on top of the chart's `Speed`s
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "mode")]
+pub enum ScrollSpeed {
+    /// Multiplies the speed of every arrow, keeping the chart's slow and fast arrows apart
+    Multiplier { multiplier: f32 },
+    /// Every arrow takes this many seconds from spawning to reaching the target, whatever its `Speed`
+    Constant { seconds: f32 },
+}
+
+impl Default for ScrollSpeed {
+    fn default() -> Self {
+        ScrollSpeed::Multiplier { multiplier: 1. }
+    }
+}
+
+impl ScrollSpeed {
+    const MULTIPLIER_STEP: f32 = 0.25;
+    const MAX_MULTIPLIER: f32 = 8.;
+    const CONSTANT_STEP: f32 = 0.1;
+    const MIN_CONSTANT: f32 = 0.3;
+    const MAX_CONSTANT: f32 = 6.;
+
+    /// Returns the speed at which an arrow of this `Speed` should move
+    pub fn value(&self, speed: Speed) -> f32 {
+        match self {
+            ScrollSpeed::Multiplier { multiplier } => speed.value() * multiplier,
+            ScrollSpeed::Constant { seconds } => DISTANCE / seconds,
+        }
+    }
+
+    /// Brings a speed read from the settings file within the limits, a speed that isn't a number
+    /// goes back to the default
+    pub fn clamped(self) -> Self {
+        match self {
+            ScrollSpeed::Multiplier { multiplier } if multiplier.is_finite() => ScrollSpeed::Multiplier {
+                multiplier: multiplier.clamp(Self::MULTIPLIER_STEP, Self::MAX_MULTIPLIER),
+            },
+            ScrollSpeed::Constant { seconds } if seconds.is_finite() => ScrollSpeed::Constant {
+                seconds: seconds.clamp(Self::MIN_CONSTANT, Self::MAX_CONSTANT),
+            },
+            _ => ScrollSpeed::default(),
+        }
+    }
+
+    /// One step faster, up to a limit
+    pub fn faster(self) -> Self {
+        match self {
+            ScrollSpeed::Multiplier { multiplier } => ScrollSpeed::Multiplier {
+                multiplier: (multiplier + Self::MULTIPLIER_STEP).min(Self::MAX_MULTIPLIER),
+            },
+            ScrollSpeed::Constant { seconds } => ScrollSpeed::Constant {
+                seconds: (seconds - Self::CONSTANT_STEP).max(Self::MIN_CONSTANT),
+            },
+        }
+    }
+
+    /// One step slower, down to a limit
+    pub fn slower(self) -> Self {
+        match self {
+            ScrollSpeed::Multiplier { multiplier } => ScrollSpeed::Multiplier {
+                multiplier: (multiplier - Self::MULTIPLIER_STEP).max(Self::MULTIPLIER_STEP),
+            },
+            ScrollSpeed::Constant { seconds } => ScrollSpeed::Constant {
+                seconds: (seconds + Self::CONSTANT_STEP).min(Self::MAX_CONSTANT),
+            },
+        }
+    }
+
+    /// Switches between multiplier and constant, keeping `Slow` arrows about as fast
+    pub fn toggle_mode(self) -> Self {
+        // Slow arrows go DISTANCE in `DISTANCE / BASE_SPEED` seconds at 1x
+        let slow_seconds = DISTANCE / BASE_SPEED;
+        match self {
+            ScrollSpeed::Multiplier { multiplier } => {
+                let steps = (slow_seconds / multiplier / Self::CONSTANT_STEP).round();
+                ScrollSpeed::Constant {
+                    seconds: (steps * Self::CONSTANT_STEP).clamp(Self::MIN_CONSTANT, Self::MAX_CONSTANT),
+                }
+            }
+            ScrollSpeed::Constant { seconds } => {
+                let steps = (slow_seconds / seconds / Self::MULTIPLIER_STEP).round();
+                ScrollSpeed::Multiplier {
+                    multiplier: (steps * Self::MULTIPLIER_STEP).clamp(Self::MULTIPLIER_STEP, Self::MAX_MULTIPLIER),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for ScrollSpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScrollSpeed::Multiplier { multiplier } => write!(f, "{:.2}x", multiplier),
+            ScrollSpeed::Constant { seconds } => write!(f, "C {:.1}s", seconds),
+        }
+    }
+}
+
+impl FromStr for Speed {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Slow" => Ok(Speed::Slow),
+            "Medium" => Ok(Speed::Medium),
+            "Fast" => Ok(Speed::Fast),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What happens when an arrow reaches the target
+#[derive(Copy, Clone, Debug, PartialEq, Default, Deserialize, Serialize)]
+pub enum NoteKind {
+    /// Has to be pressed
+    #[default]
+    Tap,
+    /// Has to be let through, pressing it is penalized
+    Mine,
+}
+
+impl NoteKind {
+    fn is_tap(&self) -> bool {
+        *self == NoteKind::Tap
+    }
+}
+
+impl FromStr for NoteKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Tap" => Ok(NoteKind::Tap),
+            "Mine" => Ok(NoteKind::Mine),
+            _ => Err(()),
+        }
+    }
+}
+
+/// An arrow as written in a chart, positioned either in seconds (`click_time`)
+/// or in beats (`beat`, needs the chart's `bpm`).
+/// Giving it an end (`end_time`, `end_beat` or `duration` in seconds) makes it a hold.
+/// It goes in a `lane`, or 4 lane charts can name it by `direction`.
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ArrowTimeToml {
-    pub click_time: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub click_time: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub beat: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_time: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_beat: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    #[serde(default, skip_serializing_if = "NoteKind::is_tap")]
+    pub kind: NoteKind,
     pub speed: Speed,
-    pub direction: Directions,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direction: Option<Directions>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lane: Option<usize>,
+}
+
+impl ArrowTimeToml {
+    /// Returns the lane the arrow goes in
+    fn lane(&self, index: usize, layout: LaneLayout) -> Result<usize, ChartLoadError> {
+        let invalid = |message| ChartLoadError::InvalidLane { arrow: index, message };
+
+        match (self.direction, self.lane) {
+            (Some(_), None) if layout.lanes() != 4 => Err(invalid("uses a direction, only 4 lane charts can")),
+            (Some(direction), None) => Ok(direction.lane()),
+            (None, Some(lane)) if lane >= layout.lanes() => Err(invalid("is past the chart's last lane")),
+            (None, Some(lane)) => Ok(lane),
+            (Some(_), Some(_)) => Err(invalid("has both a direction and a lane")),
+            (None, None) => Err(invalid("has neither a direction nor a lane")),
+        }
+    }
+
+    /// Returns the time in seconds at which the arrow should be clicked
+    fn seconds(&self, index: usize, timing: Option<&TimingMap>, offset: f64) -> Result<f64, ChartLoadError> {
+        let seconds = match (self.click_time, self.beat) {
+            (Some(click_time), None) => offset + click_time,
+            (None, Some(beat)) => match timing {
+                Some(timing) => timing.beat_to_seconds(beat),
+                None => return Err(ChartLoadError::MissingBpm { arrow: index }),
+            },
+            (Some(_), Some(_)) => return Err(ChartLoadError::AmbiguousTime { arrow: index }),
+            (None, None) => return Err(ChartLoadError::MissingTime { arrow: index }),
+        };
+
+        // Arrows are sorted by time, which needs every one of them to be a number
+        if seconds.is_finite() {
+            Ok(seconds)
+        } else {
+            Err(ChartLoadError::InvalidTime { arrow: index })
+        }
+    }
+
+    /// Returns how many seconds a hold arrow has to be held for, `None` for taps
+    fn hold_duration(
+        &self,
+        index: usize,
+        click_time: f64,
+        timing: Option<&TimingMap>,
+        offset: f64,
+    ) -> Result<Option<f64>, ChartLoadError> {
+        let invalid = |message| ChartLoadError::InvalidHold { arrow: index, message };
+
+        let end_time = match (self.end_time, self.end_beat, self.duration) {
+            (None, None, None) => return Ok(None),
+            _ if self.kind == NoteKind::Mine => return Err(invalid("is a mine, mines can't be held")),
+            (Some(end_time), None, None) => offset + end_time,
+            (None, Some(end_beat), None) => match timing {
+                Some(timing) => timing.beat_to_seconds(end_beat),
+                None => return Err(invalid("uses end_beat but the chart has no bpm")),
+            },
+            (None, None, Some(duration)) => click_time + duration,
+            _ => return Err(invalid("has more than one of end_time, end_beat and duration")),
+        };
+
+        if end_time > click_time {
+            Ok(Some(end_time - click_time))
+        } else {
+            Err(invalid("ends before it starts"))
+        }
+    }
+}
+
+/// A tempo change and/or a stop, at a given beat
+#[derive(Deserialize, Debug)]
+struct TimingToml {
+    pub beat: f64,
+    /// New beats per minute from this beat on
+    pub bpm: Option<f64>,
+    /// Seconds during which scrolling pauses on this beat
+    pub stop: Option<f64>,
+}
+
+fn default_lead_in() -> f64 {
+    DEFAULT_LEAD_IN
+}
+
+fn default_lanes() -> usize {
+    LaneLayout::default().lanes()
 }
 
 #[derive(Deserialize, Debug)]
 struct SongConfigToml {
     pub name: String,
     pub filename: String,
+    /// Beats per minute, needed by arrows positioned with `beat`
+    pub bpm: Option<f64>,
+    /// Seconds into the song where beat 0 falls, every arrow is shifted by it
+    #[serde(default)]
+    pub offset: f64,
+    /// Seconds before the song starts, giving the first arrows time to come in
+    #[serde(default = "default_lead_in")]
+    pub lead_in: f64,
+    /// Number of lanes, from 4 to 7
+    #[serde(default = "default_lanes")]
+    pub lanes: usize,
+    /// Tempo changes and stops, on top of `bpm`
+    #[serde(default)]
+    pub timing: Vec<TimingToml>,
     pub arrows: Vec<ArrowTimeToml>,
 }
 
+#[derive(Clone, Copy, Debug)]
+struct TimingSegment {
+    /// Beat at which the segment starts
+    beat: f64,
+    /// Time in seconds at which `beat` is reached
+    seconds: f64,
+    bpm: f64,
+    /// Seconds spent stopped on `beat` before moving on
+    stop: f64,
+}
+
+impl TimingSegment {
+    fn seconds_per_beat(&self) -> f64 {
+        60. / self.bpm
+    }
+}
+
+/// Converts between beats and seconds, following the chart's tempo changes and stops
+#[derive(Clone, Debug)]
+pub struct TimingMap {
+    /// Always has at least one segment, sorted by beat
+    segments: Vec<TimingSegment>,
+}
+
+impl TimingMap {
+    fn new(bpm: f64, offset: f64, timing: &[TimingToml]) -> Result<Self, ChartLoadError> {
+        // Every entry is checked before sorting, so the beats can be compared
+        for (index, change) in timing.iter().enumerate() {
+            let invalid = |message| ChartLoadError::InvalidTiming { entry: index, message };
+
+            if change.bpm.is_none() && change.stop.is_none() {
+                return Err(invalid("needs a bpm or a stop"));
+            }
+            if change.bpm.is_some_and(|bpm| !(bpm.is_finite() && bpm > 0.)) {
+                return Err(invalid("bpm must be positive"));
+            }
+            if change.stop.is_some_and(|stop| !(stop.is_finite() && stop >= 0.)) {
+                return Err(invalid("stop can't be negative"));
+            }
+            if !(change.beat.is_finite() && change.beat >= 0.) {
+                return Err(invalid("beat can't be negative"));
+            }
+        }
+
+        let mut changes = timing.iter().collect::<Vec<_>>();
+        changes.sort_by(|a, b| a.beat.total_cmp(&b.beat));
+
+        let mut segments = vec![TimingSegment { beat: 0., seconds: offset, bpm, stop: 0. }];
+        for change in changes {
+            let last = segments.last_mut().unwrap();
+            if change.beat == last.beat {
+                // Several entries on the same beat add up
+                last.bpm = change.bpm.unwrap_or(last.bpm);
+                last.stop += change.stop.unwrap_or(0.);
+                continue;
+            }
+
+            // Integrate the time spent in the previous segment
+            let seconds = last.seconds + last.stop + (change.beat - last.beat) * last.seconds_per_beat();
+            let bpm = change.bpm.unwrap_or(last.bpm);
+            segments.push(TimingSegment { beat: change.beat, seconds, bpm, stop: change.stop.unwrap_or(0.) });
+        }
+
+        Ok(Self { segments })
+    }
+
+    /// Returns the time in seconds at which `beat` is reached.
+    /// Arrows on a stopped beat land at the start of the stop.
+    pub fn beat_to_seconds(&self, beat: f64) -> f64 {
+        let segment = self
+            .segments
+            .iter()
+            .rev()
+            .find(|segment| segment.beat <= beat)
+            .unwrap_or(&self.segments[0]);
+
+        let stop = if beat > segment.beat { segment.stop } else { 0. };
+        segment.seconds + stop + (beat - segment.beat) * segment.seconds_per_beat()
+    }
+
+    /// Returns the beat reached at `seconds`, useful for syncing effects to the music
+    pub fn seconds_to_beat(&self, seconds: f64) -> f64 {
+        let segment = self
+            .segments
+            .iter()
+            .rev()
+            .find(|segment| segment.seconds <= seconds)
+            .unwrap_or(&self.segments[0]);
+
+        // Before the first segment this goes negative, keeping its tempo
+        let moving = seconds - segment.seconds;
+        let moving = if moving > 0. { (moving - segment.stop).max(0.) } else { moving };
+        segment.beat + moving / segment.seconds_per_beat()
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct ArrowTime {
-    pub spawn_time: f64,
+    /// Time at which the arrow reaches the target
+    pub click_time: f64,
     pub speed: Speed,
-    pub direction: Directions,
+    pub lane: usize,
+    pub kind: NoteKind,
+    /// Seconds the arrow has to be held for, `None` for taps
+    pub hold: Option<f64>,
 }
 
 impl ArrowTime {
-    fn new(arrow: &ArrowTimeToml) -> Self {
-        let speed_value = arrow.speed.value();
+    fn new(arrow: &ArrowTimeToml, lane: usize, click_time: f64, hold: Option<f64>) -> Self {
         Self {
-            spawn_time: arrow.click_time - (DISTANCE / speed_value) as f64,
+            click_time,
             speed: arrow.speed,
-            direction: arrow.direction
+            lane,
+            kind: arrow.kind,
+            hold,
         }
     }
+
+    /// The same arrow with its times in real seconds, for a song played at `rate`
+    pub fn at_rate(&self, rate: f64) -> Self {
+        Self {
+            click_time: self.click_time / rate,
+            hold: self.hold.map(|hold| hold / rate),
+            ..*self
+        }
+    }
+
+    /// Time at which the arrow has to spawn to reach the target on time.
+    /// Depends on the player's scroll speed, which can change during the song
+    pub fn spawn_time(&self, scroll_speed: ScrollSpeed) -> f64 {
+        self.click_time - (DISTANCE / scroll_speed.value(self.speed)) as f64
+    }
 }
 
+/// Contents of a chart file, with the audio still referenced by file name
 #[derive(Debug)]
-pub struct SongConfig {
+pub struct ChartFile {
     pub name: String,
-    pub song_audio: Handle<AudioSource>,
+    pub filename: String,
+    pub lead_in: f64,
+    pub layout: LaneLayout,
+    pub timing: Option<TimingMap>,
     pub arrows: Vec<ArrowTime>,
 }
 
-pub fn load_config(path: &str, asset_server: &AssetServer) -> SongConfig {
-    // Open file and read contents
-    let mut file = File::open(format!("assets/songs/{}", path)).expect("Couldn't open file");
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)
-        .expect("Couldn't read file into String");
+/// Everything that can go wrong while loading a chart from `assets/songs`
+#[derive(Debug)]
+pub enum ChartLoadError {
+    /// The chart file doesn't exist
+    MissingFile(PathBuf),
+    /// The chart file exists but couldn't be read
+    Io { path: PathBuf, source: io::Error },
+    /// The chart isn't valid TOML, or doesn't match the chart layout
+    Syntax { line: Option<usize>, column: Option<usize>, message: String },
+    /// An arrow uses a speed that isn't one of `Slow`, `Medium` or `Fast`
+    UnknownSpeed { arrow: usize, value: String },
+    /// An arrow uses a direction that isn't one of `Up`, `Down`, `Left` or `Right`
+    UnknownDirection { arrow: usize, value: String },
+    /// An arrow uses a kind that isn't one of `Tap` or `Mine`
+    UnknownKind { arrow: usize, value: String },
+    /// The audio file named by `filename` doesn't exist
+    MissingAudio(PathBuf),
+    /// An arrow has neither `click_time` nor `beat`
+    MissingTime { arrow: usize },
+    /// An arrow has both `click_time` and `beat`
+    AmbiguousTime { arrow: usize },
+    /// An arrow's time isn't a number, or the chart's `offset` isn't
+    InvalidTime { arrow: usize },
+    /// An arrow uses `beat` but the chart doesn't declare a `bpm`
+    MissingBpm { arrow: usize },
+    /// The chart's `bpm` isn't a positive number
+    InvalidBpm(f64),
+    /// The chart's `lead_in` is negative
+    InvalidLeadIn(f64),
+    /// The chart's `lanes` isn't a supported number of lanes
+    InvalidLanes(usize),
+    /// An arrow's lane can't be used
+    InvalidLane { arrow: usize, message: &'static str },
+    /// A `[[timing]]` entry can't be used
+    InvalidTiming { entry: usize, message: &'static str },
+    /// A hold arrow's end can't be used
+    InvalidHold { arrow: usize, message: &'static str },
+}
+
+impl fmt::Display for ChartLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChartLoadError::MissingFile(path) => write!(f, "Chart {} doesn't exist", path.display()),
+            ChartLoadError::Io { path, source } => write!(f, "Couldn't read {}: {}", path.display(), source),
+            ChartLoadError::Syntax { line: Some(line), column: Some(column), message } => {
+                write!(f, "Couldn't parse chart at line {}, column {}: {}", line, column, message)
+            }
+            ChartLoadError::Syntax { message, .. } => write!(f, "Couldn't parse chart: {}", message),
+            ChartLoadError::UnknownSpeed { arrow, value } => {
+                write!(f, "Arrow {} has unknown speed \"{}\"", arrow + 1, value)
+            }
+            ChartLoadError::UnknownDirection { arrow, value } => {
+                write!(f, "Arrow {} has unknown direction \"{}\"", arrow + 1, value)
+            }
+            ChartLoadError::UnknownKind { arrow, value } => {
+                write!(f, "Arrow {} has unknown kind \"{}\"", arrow + 1, value)
+            }
+            ChartLoadError::MissingAudio(path) => write!(f, "Audio file {} doesn't exist", path.display()),
+            ChartLoadError::MissingTime { arrow } => {
+                write!(f, "Arrow {} needs either click_time or beat", arrow + 1)
+            }
+            ChartLoadError::AmbiguousTime { arrow } => {
+                write!(f, "Arrow {} has both click_time and beat, pick one", arrow + 1)
+            }
+            ChartLoadError::InvalidTime { arrow } => {
+                write!(f, "Arrow {} has a time that isn't a number", arrow + 1)
+            }
+            ChartLoadError::MissingBpm { arrow } => {
+                write!(f, "Arrow {} is positioned in beats but the chart has no bpm", arrow + 1)
+            }
+            ChartLoadError::InvalidBpm(bpm) => write!(f, "Chart bpm must be positive, got {}", bpm),
+            ChartLoadError::InvalidLeadIn(lead_in) => {
+                write!(f, "Chart lead_in can't be negative, got {}", lead_in)
+            }
+            ChartLoadError::InvalidTiming { entry, message } => {
+                write!(f, "Timing entry {} {}", entry + 1, message)
+            }
+            ChartLoadError::InvalidHold { arrow, message } => {
+                write!(f, "Hold arrow {} {}", arrow + 1, message)
+            }
+            ChartLoadError::InvalidLanes(lanes) => write!(
+                f,
+                "Chart lanes must be from {} to {}, got {}",
+                LaneLayout::MIN_LANES,
+                LaneLayout::MAX_LANES,
+                lanes
+            ),
+            ChartLoadError::InvalidLane { arrow, message } => write!(f, "Arrow {} {}", arrow + 1, message),
+        }
+    }
+}
+
+impl std::error::Error for ChartLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ChartLoadError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<toml::de::Error> for ChartLoadError {
+    fn from(error: toml::de::Error) -> Self {
+        let mut message = error.to_string();
+
+        // toml reports zero based positions, editors show them one based
+        match error.line_col() {
+            Some((line, column)) => {
+                // Location is kept in its own fields, so drop it from the message
+                let location = format!(" at line {} column {}", line + 1, column + 1);
+                if message.ends_with(&location) {
+                    message.truncate(message.len() - location.len());
+                }
+
+                ChartLoadError::Syntax { line: Some(line + 1), column: Some(column + 1), message }
+            }
+            None => ChartLoadError::Syntax { line: None, column: None, message },
+        }
+    }
+}
+
+/// Checks speeds, directions and kinds by name, so a typo gets a better error than serde's
+fn check_arrow_names(value: &toml::Value) -> Result<(), ChartLoadError> {
+    let arrows = match value.get("arrows").and_then(toml::Value::as_array) {
+        Some(arrows) => arrows,
+        None => return Ok(()),
+    };
+
+    for (index, arrow) in arrows.iter().enumerate() {
+        if let Some(speed) = arrow.get("speed").and_then(toml::Value::as_str) {
+            if speed.parse::<Speed>().is_err() {
+                return Err(ChartLoadError::UnknownSpeed { arrow: index, value: speed.to_string() });
+            }
+        }
+
+        if let Some(direction) = arrow.get("direction").and_then(toml::Value::as_str) {
+            if direction.parse::<Directions>().is_err() {
+                return Err(ChartLoadError::UnknownDirection { arrow: index, value: direction.to_string() });
+            }
+        }
+
+        if let Some(kind) = arrow.get("kind").and_then(toml::Value::as_str) {
+            if kind.parse::<NoteKind>().is_err() {
+                return Err(ChartLoadError::UnknownKind { arrow: index, value: kind.to_string() });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn parse_chart(bytes: &[u8]) -> Result<ChartFile, ChartLoadError> {
+    let contents = std::str::from_utf8(bytes).map_err(|error| ChartLoadError::Syntax {
+        line: None,
+        column: None,
+        message: error.to_string(),
+    })?;
 
     // Parse using toml and Serde
-    let parsed: SongConfigToml = toml::from_str(&contents).expect("Couldn't parse into SongConfigToml");
+    let value: toml::Value = toml::from_str(contents)?;
+    check_arrow_names(&value)?;
+    let parsed: SongConfigToml = toml::from_str(contents)?;
+
+    if !(parsed.lead_in.is_finite() && parsed.lead_in >= 0.) {
+        return Err(ChartLoadError::InvalidLeadIn(parsed.lead_in));
+    }
+
+    let layout = LaneLayout::new(parsed.lanes).ok_or(ChartLoadError::InvalidLanes(parsed.lanes))?;
 
-    // Process Arrows
+    let timing = match parsed.bpm {
+        Some(bpm) if !(bpm.is_finite() && bpm > 0.) => return Err(ChartLoadError::InvalidBpm(bpm)),
+        Some(bpm) => Some(TimingMap::new(bpm, parsed.offset, &parsed.timing)?),
+        None if !parsed.timing.is_empty() => {
+            return Err(ChartLoadError::InvalidTiming { entry: 0, message: "needs the chart to declare a bpm" })
+        }
+        None => None,
+    };
+
+    // Process Arrows, converting beats to seconds
     let mut arrows = parsed
         .arrows
         .iter()
-        .map(|arr| ArrowTime::new(arr))
-        .collect::<Vec<ArrowTime>>();
+        .enumerate()
+        .map(|(index, arrow)| {
+            let lane = arrow.lane(index, layout)?;
+            let click_time = arrow.seconds(index, timing.as_ref(), parsed.offset)?;
+            let hold = arrow.hold_duration(index, click_time, timing.as_ref(), parsed.offset)?;
+            Ok(ArrowTime::new(arrow, lane, click_time, hold))
+        })
+        .collect::<Result<Vec<ArrowTime>, ChartLoadError>>()?;
+
+    // Sort arrows by click_time, spawn order depends on the scroll speed
+    arrows.sort_by(|a, b| a.click_time.total_cmp(&b.click_time));
+
+    Ok(ChartFile {
+        name: parsed.name,
+        filename: parsed.filename,
+        lead_in: parsed.lead_in,
+        layout,
+        timing,
+        arrows,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(beat: f64, bpm: Option<f64>, stop: Option<f64>) -> TimingToml {
+        TimingToml { beat, bpm, stop }
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn timing_round_trip_with_bpm_changes() {
+        let timing = TimingMap::new(120., 0.5, &[change(4., Some(60.), None)]).unwrap();
+
+        assert_close(timing.beat_to_seconds(0.), 0.5);
+        assert_close(timing.beat_to_seconds(2.), 1.5);
+        assert_close(timing.beat_to_seconds(4.), 2.5);
+        assert_close(timing.beat_to_seconds(6.), 4.5);
+
+        for beat in [0., 1.5, 4., 5.25, 10.] {
+            assert_close(timing.seconds_to_beat(timing.beat_to_seconds(beat)), beat);
+        }
+    }
+
+    #[test]
+    fn timing_stops_hold_the_beat() {
+        let timing = TimingMap::new(120., 0., &[change(2., None, Some(1.))]).unwrap();
+
+        // Arrows on the stopped beat land at the start of the stop
+        assert_close(timing.beat_to_seconds(2.), 1.);
+        assert_close(timing.beat_to_seconds(3.), 2.5);
+
+        assert_close(timing.seconds_to_beat(1.5), 2.);
+        assert_close(timing.seconds_to_beat(2.), 2.);
+        assert_close(timing.seconds_to_beat(2.5), 3.);
+    }
+
+    #[test]
+    fn timing_entries_can_be_out_of_order() {
+        let sorted = TimingMap::new(120., 0., &[change(4., Some(240.), None), change(8., Some(60.), None)]).unwrap();
+        let unsorted = TimingMap::new(120., 0., &[change(8., Some(60.), None), change(4., Some(240.), None)]).unwrap();
+
+        for beat in [0., 4., 6., 8., 12.] {
+            assert_close(sorted.beat_to_seconds(beat), unsorted.beat_to_seconds(beat));
+        }
+        assert_close(unsorted.beat_to_seconds(12.), 2. + 1. + 4.);
+    }
+
+    #[test]
+    fn timing_rejects_nan() {
+        let entries = [change(4., Some(60.), None), change(f64::NAN, Some(60.), None)];
+        assert!(matches!(TimingMap::new(120., 0., &entries), Err(ChartLoadError::InvalidTiming { entry: 1, .. })));
+
+        let entries = [change(4., Some(f64::NAN), None)];
+        assert!(matches!(TimingMap::new(120., 0., &entries), Err(ChartLoadError::InvalidTiming { entry: 0, .. })));
+    }
 
-    // Sort arrows by spawn_time
-    arrows.sort_by(|a, b| a.spawn_time.partial_cmp(&b.spawn_time).unwrap());
+    #[test]
+    fn chart_rejects_nan_click_time() {
+        let chart = r#"
+            name = "Test"
+            filename = "test.mp3"
+            arrows = [
+                { click_time = 1.0, speed = "Slow", direction = "Up" },
+                { click_time = nan, speed = "Slow", direction = "Up" },
+            ]
+        "#;
 
-    // Load song audio and get the handle
-    let song_audio = asset_server.load(&*format!("songs/{}", parsed.filename));
+        assert!(matches!(parse_chart(chart.as_bytes()), Err(ChartLoadError::InvalidTime { arrow: 1 })));
+    }
+
+    #[test]
+    fn scroll_speed_is_clamped_when_loaded() {
+        assert_eq!(ScrollSpeed::Multiplier { multiplier: 0. }.clamped(), ScrollSpeed::Multiplier { multiplier: 0.25 });
+        assert_eq!(ScrollSpeed::Multiplier { multiplier: -2. }.clamped(), ScrollSpeed::Multiplier { multiplier: 0.25 });
+        assert_eq!(ScrollSpeed::Multiplier { multiplier: 100. }.clamped(), ScrollSpeed::Multiplier { multiplier: 8. });
+        assert_eq!(ScrollSpeed::Multiplier { multiplier: f32::NAN }.clamped(), ScrollSpeed::default());
+        assert_eq!(ScrollSpeed::Constant { seconds: 0. }.clamped(), ScrollSpeed::Constant { seconds: 0.3 });
+        assert_eq!(ScrollSpeed::Constant { seconds: f32::INFINITY }.clamped(), ScrollSpeed::default());
+        assert_eq!(ScrollSpeed::Constant { seconds: 2. }.clamped(), ScrollSpeed::Constant { seconds: 2. });
+    }
 
-    SongConfig { name: parsed.name, song_audio, arrows }
+    #[test]
+    fn scroll_speed_steps_stay_within_limits() {
+        let multiplier = ScrollSpeed::Multiplier { multiplier: 1. };
+        assert_eq!(multiplier.faster(), ScrollSpeed::Multiplier { multiplier: 1.25 });
+        assert_eq!(multiplier.slower(), ScrollSpeed::Multiplier { multiplier: 0.75 });
+        assert_eq!(ScrollSpeed::Multiplier { multiplier: 0.25 }.slower(), ScrollSpeed::Multiplier { multiplier: 0.25 });
+        assert_eq!(ScrollSpeed::Multiplier { multiplier: 8. }.faster(), ScrollSpeed::Multiplier { multiplier: 8. });
+
+        // Constant speeds are in seconds, fewer of them is faster
+        let ScrollSpeed::Constant { seconds } = ScrollSpeed::Constant { seconds: 2. }.faster() else { unreachable!() };
+        assert!((seconds - 1.9).abs() < 1e-6);
+        assert_eq!(ScrollSpeed::Constant { seconds: 0.3 }.faster(), ScrollSpeed::Constant { seconds: 0.3 });
+        assert_eq!(ScrollSpeed::Constant { seconds: 6. }.slower(), ScrollSpeed::Constant { seconds: 6. });
+    }
+
+    #[test]
+    fn scroll_speed_toggle_keeps_slow_arrows_as_fast() {
+        let multiplier = ScrollSpeed::Multiplier { multiplier: 2. };
+        let constant = multiplier.toggle_mode();
+
+        assert_eq!(constant, ScrollSpeed::Constant { seconds: 1.5 });
+        assert!((constant.value(Speed::Slow) - multiplier.value(Speed::Slow)).abs() < 1e-3);
+        assert_eq!(constant.toggle_mode(), multiplier);
+
+        // Constant speeds past what multipliers reach are clamped
+        assert_eq!(ScrollSpeed::Constant { seconds: 0.3 }.toggle_mode(), ScrollSpeed::Multiplier { multiplier: 8. });
+    }
 }
@@ -1,14 +1,36 @@
 use bevy::prelude::*;
-use crate::{ScoreResource, consts::AppState, time::ControlledTime};
+use crate::{
+    ScoreResource,
+    arrows::{CorrectArrowEvent, MissedArrowEvent},
+    consts::{AppState, TARGET_POSITION},
+    score::{GradeThresholds, Judgement},
+    settings::{ScrollDirection, Settings},
+    time::ControlledTime,
+};
+
+/// Seconds the judgement text stays on screen
+const JUDGEMENT_TEXT_SECONDS: f64 = 0.6;
+
+/// Seconds the combo text takes to shrink back after changing
+const COMBO_POP_SECONDS: f64 = 0.15;
+
+/// Everything on screen during the song, removed once it's over
+#[derive(Component)]
+struct GameUI;
 
 #[derive(Component)]
 struct TimeText;
 
+#[derive(Component)]
+struct ScrollSpeedText;
+
 fn setup_ui(
     mut commands: Commands,
     asset_server: ResMut<AssetServer>,
+    settings: Res<Settings>,
 ) {
     let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    let scroll = settings.scroll;
 
     commands.spawn_bundle(NodeBundle {
         style: Style {
@@ -18,11 +40,12 @@ fn setup_ui(
                 top: Val::Px(10.),
                 ..Default::default()
             },
+            flex_direction: FlexDirection::ColumnReverse,
             ..Default::default()
         },
         color: UiColor(Color::NONE),
         ..Default::default()
-    }).add_children(|parent| {
+    }).insert(GameUI).add_children(|parent| {
         parent
             .spawn_bundle(TextBundle::from_section(
                 "Time: 0.0",
@@ -34,6 +57,16 @@ fn setup_ui(
                 },
             ))
             .insert(TimeText);
+        parent
+            .spawn_bundle(TextBundle::from_section(
+                format!("Speed: {}", settings.scroll_speed),
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 25.0,
+                    color: Color::rgb(0.8, 0.8, 0.8),
+                },
+            ))
+            .insert(ScrollSpeedText);
         }
     );
 
@@ -49,24 +82,103 @@ fn setup_ui(
         },
         color: UiColor(Color::NONE),
         ..Default::default()
-    }).add_children(|parent| {
+    }).insert(GameUI).add_children(|parent| {
         parent.spawn_bundle(TextBundle::from_section(
-            "Score: 0. Corrects: 0. Fails: 0",
+            "Score: 0. Corrects: 0. Fails: 0. Max combo: 0\nHolds: 0. Dropped holds: 0. Mines hit: 0\n",
             TextStyle {
                 font: font.clone(),
                 color: Color::rgb(0.8, 0.8, 0.8),
-                font_size: 40.0,
+                font_size: 30.0,
                 ..Default::default()
             },
         ))
         .insert(ScoreText);
     });
-    
+
+    commands.spawn_bundle(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                right: Val::Px(10.),
+                top: Val::Px(10.),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        color: UiColor(Color::NONE),
+        ..Default::default()
+    }).insert(GameUI).add_children(|parent| {
+        parent.spawn_bundle(TextBundle::from_section(
+            "Accuracy: 100.00% (SS)",
+            TextStyle {
+                font: font.clone(),
+                color: Color::rgb(0.9, 0.9, 0.9),
+                font_size: 40.0,
+            },
+        ))
+        .insert(AccuracyText);
+    });
+
+    // Above the target arrows, or just before them in the middle when scrolling vertically
+    let judgement_position = match scroll {
+        ScrollDirection::Horizontal => scroll.place(TARGET_POSITION, 250.),
+        ScrollDirection::Upscroll | ScrollDirection::Downscroll => scroll.place(TARGET_POSITION - 150., 0.),
+    };
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 40.0,
+                    color: Color::NONE,
+                },
+            )
+            .with_alignment(TextAlignment::CENTER),
+            transform: Transform::from_translation(judgement_position.extend(10.)),
+            ..Default::default()
+        })
+        .insert(JudgementText { shown_at: -JUDGEMENT_TEXT_SECONDS, color: Color::NONE })
+        .insert(GameUI);
+
+    // In the middle of the lanes, behind the arrows
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_sections([
+                TextSection::new(
+                    "",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 90.0,
+                        color: Color::rgba(0.9, 0.9, 0.9, 0.7),
+                    },
+                ),
+                TextSection::new(
+                    "",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 30.0,
+                        color: Color::rgba(0.9, 0.9, 0.9, 0.7),
+                    },
+                ),
+            ])
+            .with_alignment(TextAlignment::CENTER),
+            transform: Transform::from_translation(scroll.place(-100., 0.).extend(0.2)),
+            ..Default::default()
+        })
+        .insert(ComboText { combo: 0, changed_at: -COMBO_POP_SECONDS })
+        .insert(GameUI);
+}
+
+fn tear_down_ui(mut commands: Commands, query: Query<Entity, With<GameUI>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
 }
 
 fn update_time_text(time: Res<ControlledTime>, mut query: Query<(&mut Text, With<TimeText>)>) {
-    // Song starts 3 seconds after real time
-    let secs = time.seconds_since_startup() - 3.;
+    // Where the song is at, whatever the playback rate
+    let secs = time.chart_seconds();
 
     // Don't do anything before the song starts
     if secs < 0. {
@@ -78,9 +190,30 @@ fn update_time_text(time: Res<ControlledTime>, mut query: Query<(&mut Text, With
     }
 }
 
+fn update_scroll_speed_text(settings: Res<Settings>, mut query: Query<&mut Text, With<ScrollSpeedText>>) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    for mut text in query.iter_mut() {
+        text.sections[0].value = format!("Speed: {}", settings.scroll_speed);
+    }
+}
+
 #[derive(Component)]
 struct ScoreText;
 
+/// Lists how many presses got each judgement
+fn judgement_counts(score: &ScoreResource) -> String {
+    use Judgement::*;
+
+    [Perfect, Great, Good, Bad, Miss]
+        .iter()
+        .map(|judgement| format!("{}: {}", judgement.name(), score.judgements(*judgement)))
+        .collect::<Vec<_>>()
+        .join(". ")
+}
+
 fn update_score_text(score: Res<ScoreResource>, mut query: Query<(&mut Text, With<ScoreText>)>) {
     if !score.is_changed() {
         return;
@@ -88,25 +221,140 @@ fn update_score_text(score: Res<ScoreResource>, mut query: Query<(&mut Text, Wit
     
     for (mut text, _marker) in query.iter_mut() {
         text.sections[0].value = format!(
-            "Score: {}. Corrects: {}. Fails: {}",
+            "Score: {}. Corrects: {}. Fails: {}. Max combo: {}\nHolds: {}. Dropped holds: {}. Mines hit: {}\n{}",
             score.score(),
             score.corrects(),
-            score.fails()
+            score.fails(),
+            score.max_combo(),
+            score.holds(),
+            score.dropped_holds(),
+            score.mines_hit(),
+            judgement_counts(&score)
         );
     }
 }
 
+#[derive(Component)]
+struct AccuracyText;
+
+fn update_accuracy_text(
+    score: Res<ScoreResource>,
+    thresholds: Res<GradeThresholds>,
+    mut query: Query<&mut Text, With<AccuracyText>>,
+) {
+    if !score.is_changed() {
+        return;
+    }
+
+    let accuracy = score.accuracy();
+    for mut text in query.iter_mut() {
+        text.sections[0].value = format!("Accuracy: {:.2}% ({})", accuracy, thresholds.grade(accuracy).name());
+    }
+}
+
+#[derive(Component)]
+struct JudgementText {
+    shown_at: f64,
+    color: Color,
+}
+
+fn judgement_color(judgement: Judgement) -> Color {
+    match judgement {
+        Judgement::Perfect => Color::rgb(1., 0.95, 0.3),
+        Judgement::Great => Color::rgb(0.4, 0.9, 0.3),
+        Judgement::Good => Color::rgb(0.3, 0.7, 1.),
+        Judgement::Bad => Color::rgb(0.7, 0.5, 0.8),
+        Judgement::Miss => Color::rgb(0.9, 0.2, 0.2),
+    }
+}
+
+/// Shows the judgement of the latest press near the target arrows
+fn show_judgement_text(
+    time: Res<Time>,
+    mut correct_events: EventReader<CorrectArrowEvent>,
+    mut missed_events: EventReader<MissedArrowEvent>,
+    mut query: Query<(&mut Text, &mut JudgementText)>,
+) {
+    let judgement = correct_events
+        .iter()
+        .map(|event| event.judgement)
+        .chain(missed_events.iter().map(|_| Judgement::Miss))
+        .last();
+
+    if let Some(judgement) = judgement {
+        for (mut text, mut judgement_text) in query.iter_mut() {
+            text.sections[0].value = judgement.name().to_string();
+            judgement_text.shown_at = time.seconds_since_startup();
+            judgement_text.color = judgement_color(judgement);
+        }
+    }
+}
+
+/// Pops the judgement text in and fades it out
+fn fade_judgement_text(time: Res<Time>, mut query: Query<(&mut Text, &mut Transform, &JudgementText)>) {
+    for (mut text, mut transform, judgement_text) in query.iter_mut() {
+        let progress = ((time.seconds_since_startup() - judgement_text.shown_at) / JUDGEMENT_TEXT_SECONDS)
+            .clamp(0., 1.) as f32;
+
+        text.sections[0].style.color = *judgement_text.color.clone().set_a(1. - progress);
+        transform.scale = Vec3::splat(1. + 0.3 * (1. - progress * 6.).max(0.));
+    }
+}
+
+#[derive(Component)]
+struct ComboText {
+    combo: usize,
+    changed_at: f64,
+}
+
+/// Shows the current combo, popping when it goes up and hiding when it breaks
+fn update_combo_text(
+    time: Res<Time>,
+    score: Res<ScoreResource>,
+    mut query: Query<(&mut Text, &mut Transform, &mut ComboText)>,
+) {
+    for (mut text, mut transform, mut combo_text) in query.iter_mut() {
+        if combo_text.combo != score.combo() {
+            combo_text.combo = score.combo();
+            combo_text.changed_at = time.seconds_since_startup();
+
+            // A single hit isn't much of a combo
+            let (number, label) = if score.combo() >= 2 {
+                (format!("{}\n", score.combo()), "combo")
+            } else {
+                (String::new(), "")
+            };
+            text.sections[0].value = number;
+            text.sections[1].value = label.to_string();
+        }
+
+        let progress = ((time.seconds_since_startup() - combo_text.changed_at) / COMBO_POP_SECONDS)
+            .clamp(0., 1.) as f32;
+        transform.scale = Vec3::splat(1. + 0.25 * (1. - progress));
+    }
+}
+
 pub  struct UIPlugin;
 impl Plugin for UIPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(
+        app.init_resource::<GradeThresholds>()
+            .add_system_set(
                 SystemSet::on_enter(AppState::Game)
                     .with_system(setup_ui)
             )
             .add_system_set(
                 SystemSet::on_update(AppState::Game)
                     .with_system(update_time_text)
-                    .with_system(update_score_text)   
+                    .with_system(update_scroll_speed_text)
+                    .with_system(update_score_text)
+                    .with_system(show_judgement_text)
+                    .with_system(fade_judgement_text.after(show_judgement_text))
+                    .with_system(update_combo_text)
+                    .with_system(update_accuracy_text)
+            )
+            .add_system_set(
+                SystemSet::on_exit(AppState::Game)
+                    .with_system(tear_down_ui)
             );
     }
 }